@@ -0,0 +1,55 @@
+use crate::error::{Result, SortError};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the categorization cache kept inside the target directory
+pub const CACHE_FILE: &str = ".sortify-cache.json";
+
+/// Model answers from earlier runs, keyed by a hash of model name and filename so that
+/// re-runs skip files the model has already categorized
+pub struct Cache {
+    path: PathBuf,
+    model: String,
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl Cache {
+    /// Loads the cache in `target_dir`. A missing or unreadable cache just starts out empty.
+    pub fn load(target_dir: &Path, model: &str) -> Self {
+        let path = target_dir.join(CACHE_FILE);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Cache { path, model: model.to_string(), entries: Mutex::new(entries) }
+    }
+
+    pub fn clear(target_dir: &Path) -> Result<()> {
+        let path = target_dir.join(CACHE_FILE);
+        if path.exists() {
+            fs::remove_file(&path).map_err(SortError::io("Failed to remove cache"))?;
+        }
+        Ok(())
+    }
+
+    fn key(&self, filename: &str) -> String {
+        blake3::hash(format!("{}\0{}", self.model, filename).as_bytes()).to_hex().to_string()
+    }
+
+    pub fn get(&self, filename: &str) -> Option<String> {
+        self.entries.lock().unwrap().get(&self.key(filename)).cloned()
+    }
+
+    /// Adds filename -> category answers and writes the whole cache back to disk
+    pub fn store<'a>(&self, answers: impl Iterator<Item = (&'a str, &'a String)>) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for (filename, category) in answers {
+            entries.insert(self.key(filename), category.clone());
+        }
+        let contents = serde_json::to_string(&*entries).map_err(io::Error::from).map_err(SortError::io("Failed to write cache"))?;
+        fs::write(&self.path, contents).map_err(SortError::io("Failed to write cache"))
+    }
+}
@@ -0,0 +1,447 @@
+use crate::config::{CategoryCase, SortConfig};
+use crate::error::{Result, SortError};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Tidies a category name from the model before it's sanitized: whitespace is trimmed and collapsed,
+/// and the casing is changed as `--category-case` asks.
+pub fn normalize_category(category: &str, case: CategoryCase) -> String {
+    let words = category.split_whitespace().collect::<Vec<_>>().join(" ");
+    match case {
+        CategoryCase::AsIs => words,
+        CategoryCase::Lower => words.to_lowercase(),
+        CategoryCase::Title => {
+            let mut title = String::with_capacity(words.len());
+            let mut word_start = true;
+            for c in words.chars() {
+                if word_start {
+                    title.extend(c.to_uppercase());
+                } else {
+                    title.extend(c.to_lowercase());
+                }
+                word_start = c == ' ' || c == '/';
+            }
+            title
+        }
+    }
+}
+
+/// The category `aliases` (`--alias`) map `category` to, or `category` itself. The left-hand sides match
+/// regardless of case and spacing.
+pub fn resolve_alias<'a>(category: &'a str, aliases: &'a [(String, String)]) -> &'a str {
+    let key = normalize_category(category, CategoryCase::Lower);
+    aliases.iter()
+        .find(|(from, _)| normalize_category(from, CategoryCase::Lower) == key)
+        .map_or(category, |(_, to)| to.as_str())
+}
+
+/// The names of the directories already in `dir`, sorted, leaving out hidden ones
+pub fn existing_categories(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    names
+}
+
+/// How similar, from 0 to 1, a category must be to an allowed one (`--categories`) to be taken for it
+const CLOSE_MATCH: f64 = 0.85;
+
+/// The entry of `allowed` (`--categories`) that `category` stands for, such as `Invoices` for `Invoice`.
+/// `None` if nothing is close enough.
+pub fn closest_allowed<'a>(category: &str, allowed: &'a [String]) -> Option<&'a str> {
+    closest_match(category, allowed, CLOSE_MATCH)
+}
+
+/// The entry of `candidates` equal to `category` regardless of case and spacing, or else the most similar one
+/// by Jaro-Winkler similarity, which favours a shared start like `Photos` and `Photographs`, as long as that is
+/// at least `threshold`
+pub fn closest_match<'a>(category: &str, candidates: &'a [String], threshold: f64) -> Option<&'a str> {
+    let key = normalize_category(category, CategoryCase::Lower);
+    let keyed = || candidates.iter().map(|candidate| (candidate.as_str(), normalize_category(candidate, CategoryCase::Lower)));
+    if let Some((exact, _)) = keyed().find(|(_, candidate)| *candidate == key) {
+        return Some(exact);
+    }
+    keyed()
+        .map(|(candidate, normalized)| (candidate, strsim::jaro_winkler(&key, &normalized)))
+        .filter(|(_, similarity)| *similarity >= threshold)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(candidate, _)| candidate)
+}
+
+/// Characters that can't appear in a file name on this OS, besides the `/` between category levels
+#[cfg(windows)]
+const ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+#[cfg(not(windows))]
+const ILLEGAL_CHARS: &[char] = &[];
+
+/// Device names Windows reserves in every directory, whatever the extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What [`sanitize_category`] allows in a folder name
+#[derive(Clone, Debug, Default)]
+pub struct SanitizePolicy {
+    /// Stand-in for characters that can't be used (`--replace-char`); they're dropped without one
+    pub replace_char: Option<char>,
+    /// Treat spaces like characters that can't be used (`--no-spaces`)
+    pub no_spaces: bool,
+    /// Longest a single level of a category may be, in characters (`--max-category-len`)
+    pub max_len: Option<usize>,
+    /// Strip slashes rather than nesting categories (`--flat`)
+    pub flat: bool,
+}
+
+impl SanitizePolicy {
+    pub fn from_config(config: &SortConfig) -> Self {
+        SanitizePolicy {
+            replace_char: config.replace_char,
+            no_spaces: config.no_spaces,
+            max_len: config.max_category_len,
+            flat: config.flat,
+        }
+    }
+}
+
+/// Fails if `c` can't stand in for illegal characters, because it would be illegal (or a separator) itself
+pub fn validate_replace_char(c: char) -> Result<()> {
+    if c == '/' || c == '.' || c.is_control() || c.is_whitespace() || ILLEGAL_CHARS.contains(&c) {
+        return Err(SortError::InvalidConfig(format!("'{}' can't be used as the replacement character", c.escape_default())));
+    }
+    Ok(())
+}
+
+/// Turns a category name from the model into a safe `/`-separated category path. Each component keeps any
+/// letters (in any script), digits, spaces, punctuation and emoji, dropping (or replacing, as `policy` says) only
+/// control characters and those illegal in file names on this OS. Leading dots are dropped too, so a component
+/// can be neither hidden nor `..`, and a category can never escape the target directory. With `policy.flat`,
+/// slashes are stripped, giving a single directory. A name left empty becomes `Other`.
+pub fn sanitize_category(category: &str, policy: &SanitizePolicy) -> String {
+    let components: Vec<String> = if policy.flat {
+        vec![sanitize_component(&category.replace('/', ""), policy)]
+    } else {
+        category.split('/').map(|component| sanitize_component(component, policy)).collect()
+    };
+
+    let components: Vec<String> = components.into_iter().filter(|c| !c.is_empty()).collect();
+    if components.is_empty() { "Other".to_string() } else { components.join("/") }
+}
+
+/// Turns a filename the model suggested (`--rename`) into a safe name for the file called `original`, by the
+/// same rules as a level of a category path. The original extension is added back if the suggestion lost it.
+/// Returns `None` if nothing usable is left.
+pub fn sanitize_filename(suggested: &str, original: &str, policy: &SanitizePolicy) -> Option<String> {
+    let policy = SanitizePolicy { max_len: None, ..policy.clone() };
+    let name = sanitize_component(&suggested.replace(['/', '\\'], ""), &policy);
+    if name.is_empty() {
+        return None;
+    }
+
+    let extension = |name: &str| Path::new(name).extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension(original) {
+        Some(original_ext) if extension(&name).as_ref() != Some(&original_ext) => {
+            let ext = Path::new(original).extension().unwrap_or_default().to_string_lossy();
+            Some(format!("{}.{}", name, ext))
+        }
+        _ => Some(name),
+    }
+}
+
+/// Sanitizes a single level of a category path. Whitespace is collapsed to single spaces, and trailing dots
+/// and spaces go as well since Windows can't keep them. On Windows, reserved device names like `CON` get a
+/// trailing underscore.
+fn sanitize_component(component: &str, policy: &SanitizePolicy) -> String {
+    let words = component.split_whitespace().collect::<Vec<_>>().join(" ");
+    let kept: String = words.chars()
+        .filter(|c| !c.is_control())
+        .filter_map(|c| {
+            let illegal = ILLEGAL_CHARS.contains(&c) || (policy.no_spaces && c == ' ');
+            if illegal { policy.replace_char } else { Some(c) }
+        })
+        .collect();
+
+    let trim = |name: &str| name.trim_start_matches('.').trim_end_matches(['.', ' ']).to_string();
+    let mut name = trim(&kept);
+    if let Some(max_len) = policy.max_len {
+        name = trim(&name.chars().take(max_len).collect::<String>());
+    }
+    if cfg!(windows) && is_reserved_name(&name) {
+        name.push('_');
+    }
+    name
+}
+
+/// Whether Windows reserves `name`, as `CON` or `con.txt` would be
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Picks the categories to fold into `misc` so that at most `max` remain, given how many files each has.
+/// The least populated go first (ties broken by name), and `misc` itself is never folded. Folding into a
+/// `misc` that isn't among the categories yet adds it, so one more has to go.
+pub fn categories_to_fold(counts: &HashMap<String, usize>, max: usize, misc: &str) -> HashSet<String> {
+    let mut by_size: Vec<(&String, usize)> = counts.iter()
+        .filter(|(category, _)| *category != misc)
+        .map(|(category, count)| (category, *count))
+        .collect();
+    by_size.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut remaining = counts.len();
+    let mut has_misc = counts.contains_key(misc);
+    let mut folded = HashSet::new();
+    for (category, _) in by_size {
+        if remaining <= max {
+            break;
+        }
+        folded.insert(category.clone());
+        if has_misc {
+            remaining -= 1;
+        }
+        has_misc = true;
+    }
+    folded
+}
+
+/// The spellings of the categories in use, keyed by their lowercase form, so that `music` joins an existing
+/// `Music` instead of creating a near-duplicate folder. Shared by all batches of a run.
+pub struct CategoryNames(Mutex<HashMap<String, String>>);
+
+impl CategoryNames {
+    /// Starts from the directories already in `target_dir`, which win over any other spelling
+    pub fn load(target_dir: &Path) -> Self {
+        let names = existing_categories(target_dir).into_iter().map(|name| (name.to_lowercase(), name)).collect();
+        CategoryNames(Mutex::new(names))
+    }
+
+    /// The spelling to use for a sanitized, `/`-separated category path. Each level is matched on its own,
+    /// so `media/music` becomes `Media/music` when only `Media` exists. The first spelling seen for a new
+    /// category becomes the one used from then on.
+    pub fn canonical(&self, category: &str) -> String {
+        let mut names = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canonical = String::new();
+        for component in category.split('/') {
+            let path = if canonical.is_empty() { component.to_string() } else { format!("{}/{}", canonical, component) };
+            canonical = names.entry(path.to_lowercase()).or_insert(path).clone();
+        }
+        canonical
+    }
+}
+
+/// Turns the categories of a run, from the model or typed in during a `--tui` review, into the category paths it
+/// uses: aliases resolved, held to `--categories`, cased, put into a close existing folder with `--fuzzy-match`,
+/// sanitized, and spelled like the categories already in use
+pub struct CategoryPaths<'a> {
+    config: &'a SortConfig,
+    /// Spellings of the categories in use, so near-duplicates merge
+    names: CategoryNames,
+    /// The folders in the destination when the run started, for `--fuzzy-match`
+    existing_folders: Vec<String>,
+}
+
+/// A category as [`CategoryPaths::resolve`] placed it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedCategory {
+    /// The sanitized, `/`-separated category path
+    pub path: String,
+    /// Whether `--categories` doesn't allow the category, so that the path is the fallback's
+    pub disallowed: bool,
+    /// The top level of the category and the existing folder `--fuzzy-match` put it into, if it did
+    pub snapped: Option<(String, String)>,
+}
+
+impl<'a> CategoryPaths<'a> {
+    /// Starts from the folders already in the destination of `config`
+    pub fn new(config: &'a SortConfig) -> Self {
+        let dest = config.destination();
+        CategoryPaths {
+            config,
+            names: CategoryNames::load(dest),
+            existing_folders: if config.fuzzy_match { existing_categories(dest) } else { Vec::new() },
+        }
+    }
+
+    /// The category path to use for `category`
+    pub fn resolve(&self, category: &str) -> ResolvedCategory {
+        let config = self.config;
+        let mut category = resolve_alias(category, &config.alias);
+        let mut disallowed = false;
+        if !config.categories.is_empty() && category != config.fallback_category {
+            category = closest_allowed(category, &config.categories).unwrap_or_else(|| {
+                disallowed = true;
+                &config.fallback_category
+            });
+        }
+        let mut normalized = normalize_category(category, config.category_case);
+        let mut snapped = None;
+        if config.fuzzy_match {
+            // Only the top level can match, as the existing folders are only looked up there
+            let (top, rest) = normalized.split_once('/').map_or((normalized.as_str(), None), |(top, rest)| (top, Some(rest)));
+            let folder = closest_match(top, &self.existing_folders, config.fuzzy_threshold)
+                .filter(|folder| !folder.eq_ignore_ascii_case(top));
+            if let Some(folder) = folder {
+                snapped = Some((top.to_string(), folder.to_string()));
+                normalized = rest.map_or(folder.to_string(), |rest| format!("{}/{}", folder, rest));
+            }
+        }
+        let path = self.canonical(&sanitize_category(&normalized, &SanitizePolicy::from_config(config)));
+        ResolvedCategory { path, disallowed, snapped }
+    }
+
+    /// The spelling to use for an already sanitized category path; see [`CategoryNames::canonical`]
+    pub fn canonical(&self, category: &str) -> String {
+        self.names.canonical(category)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(category: &str) -> String {
+        sanitize_category(category, &SanitizePolicy::default())
+    }
+
+    #[test]
+    fn nothing_usable_gives_other() {
+        assert_eq!(sanitize(""), "Other");
+        assert_eq!(sanitize("   "), "Other");
+        assert_eq!(sanitize("..."), "Other");
+        assert_eq!(sanitize("/ /"), "Other");
+    }
+
+    #[test]
+    fn leading_dots_and_traversal_are_stripped() {
+        assert_eq!(sanitize(".hidden"), "hidden");
+        assert_eq!(sanitize("../etc"), "etc");
+        assert_eq!(sanitize("Music/../Photos"), "Music/Photos");
+        assert_eq!(sanitize("Docs. "), "Docs");
+    }
+
+    #[test]
+    fn whitespace_and_control_characters_are_cleaned() {
+        assert_eq!(sanitize("  My \t Photos\n"), "My Photos");
+        assert_eq!(sanitize("Tax\u{7}es"), "Taxes");
+    }
+
+    #[test]
+    fn unicode_names_come_through_unchanged() {
+        assert_eq!(sanitize("音楽/ポップ"), "音楽/ポップ");
+        assert_eq!(sanitize("📷 Photos"), "📷 Photos");
+        assert_eq!(sanitize("Café Reçus"), "Café Reçus");
+        assert_eq!(sanitize("Ελληνικά"), "Ελληνικά");
+    }
+
+    #[test]
+    fn max_len_counts_characters_not_bytes() {
+        let policy = SanitizePolicy { max_len: Some(2), ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("音楽ファイル", &policy), "音楽");
+        assert_eq!(sanitize_category("📷📷📷", &policy), "📷📷");
+    }
+
+    #[test]
+    fn flat_strips_slashes() {
+        let policy = SanitizePolicy { flat: true, ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("Music/Rock", &policy), "MusicRock");
+    }
+
+    #[test]
+    fn no_spaces_drops_or_replaces_them() {
+        let policy = SanitizePolicy { no_spaces: true, ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("My Photos", &policy), "MyPhotos");
+        let policy = SanitizePolicy { no_spaces: true, replace_char: Some('_'), ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("My Photos/Old Ones", &policy), "My_Photos/Old_Ones");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn replace_char_stands_in_for_illegal_characters() {
+        let policy = SanitizePolicy { replace_char: Some('_'), ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("Q1: Reports?", &policy), "Q1_ Reports_");
+        assert_eq!(sanitize("Q1: Reports?"), "Q1 Reports");
+    }
+
+    #[test]
+    fn max_len_cuts_without_leaving_a_trailing_dot_or_space() {
+        let policy = SanitizePolicy { max_len: Some(9), ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("Invoices 2024", &policy), "Invoices");
+        assert_eq!(sanitize_category("Receipts.2024", &policy), "Receipts");
+        assert_eq!(sanitize_category("Music/Classical", &policy), "Music/Classical");
+        let policy = SanitizePolicy { max_len: Some(5), ..SanitizePolicy::default() };
+        assert_eq!(sanitize_category("Music/Classical", &policy), "Music/Class");
+    }
+
+    fn config_in(dir: &Path) -> SortConfig {
+        SortConfig { target_dir: dir.to_string_lossy().to_string(), ..SortConfig::default() }
+    }
+
+    #[test]
+    fn resolve_holds_to_the_allowed_categories() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SortConfig { categories: vec!["Invoices".to_string()], ..config_in(dir.path()) };
+        let paths = CategoryPaths::new(&config);
+        assert_eq!(paths.resolve("invoice").path, "Invoices");
+        let typo = paths.resolve("Snacks");
+        assert!(typo.disallowed);
+        assert_eq!(typo.path, config.fallback_category);
+    }
+
+    #[test]
+    fn resolve_spells_like_existing_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Music")).unwrap();
+        let config = config_in(dir.path());
+        let paths = CategoryPaths::new(&config);
+        assert_eq!(paths.resolve("music/rock").path, "Music/rock");
+        assert_eq!(paths.resolve("photos").path, "photos");
+        assert_eq!(paths.resolve("Photos").path, "photos");
+    }
+
+    #[test]
+    fn resolve_snaps_to_close_folders_with_fuzzy_match() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Photos")).unwrap();
+        let config = SortConfig { fuzzy_match: true, ..config_in(dir.path()) };
+        let resolved = CategoryPaths::new(&config).resolve("Photographs");
+        assert_eq!(resolved.path, "Photos");
+        assert_eq!(resolved.snapped, Some(("Photographs".to_string(), "Photos".to_string())));
+    }
+
+    #[test]
+    fn replace_char_is_validated() {
+        assert!(validate_replace_char('_').is_ok());
+        assert!(validate_replace_char('/').is_err());
+        assert!(validate_replace_char('.').is_err());
+        assert!(validate_replace_char(' ').is_err());
+    }
+
+    #[test]
+    fn reserved_names_are_recognized_on_any_platform() {
+        assert!(is_reserved_name("CON"));
+        assert!(is_reserved_name("con.txt"));
+        assert!(is_reserved_name("Lpt1 .tar.gz"));
+        assert!(is_reserved_name("nul"));
+        assert!(!is_reserved_name("Console"));
+        assert!(!is_reserved_name("COM10"));
+        assert!(!is_reserved_name("my.CON"));
+        assert!(!is_reserved_name(""));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn reserved_windows_names_get_a_suffix() {
+        assert_eq!(sanitize("CON"), "CON_");
+        assert_eq!(sanitize("con.txt"), "con.txt_");
+        assert_eq!(sanitize("Music/LPT1"), "Music/LPT1_");
+        assert_eq!(sanitize("Console"), "Console");
+    }
+}
@@ -0,0 +1,460 @@
+use crate::cache::CACHE_FILE;
+use crate::config::{FileOrder, SortConfig};
+use crate::error::{Result, SortError};
+use crate::journal::{Journal, JOURNAL_FILE};
+use crate::learn::LEARNED_FILE;
+use crate::metadata::{is_date_category, size_bucket_names, DEFAULT_EXIF_DATE_FORMAT, DEFAULT_MTIME_DATE_FORMAT};
+use crate::category::{sanitize_category, SanitizePolicy};
+use crate::rules::Rules;
+use crate::sort::{file_name_string, DUPLICATES_CATEGORY, EMPTY_CATEGORY, MISC_CATEGORY};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Files the tool itself keeps in the target directory, which are never sorted
+const TOOL_FILES: &[&str] = &[JOURNAL_FILE, CACHE_FILE, LEARNED_FILE, IGNORE_FILE];
+
+/// Name of the file listing, in gitignore syntax, what in its directory and below is never sorted
+pub const IGNORE_FILE: &str = ".sortifyignore";
+
+/// Name of git's ignore files, which `--respect-gitignore` reads as well
+const GITIGNORE_FILE: &str = ".gitignore";
+
+/// Decides which collected files are eligible for sorting, based on their filename and the top-level directory
+/// they're in
+pub struct FileFilter {
+    exclude: GlobSet,
+    include: Option<GlobSet>,
+    /// Lowercased extensions without the leading dot
+    extensions: Option<HashSet<String>>,
+    /// Files must have been modified before this (`--older-than`)
+    older_than: Option<SystemTime>,
+    /// Files must have been modified after this (`--newer-than`)
+    newer_than: Option<SystemTime>,
+    /// Smallest file size in bytes (`--min-size`)
+    min_size: Option<u64>,
+    /// Largest file size in bytes (`--max-size`)
+    max_size: Option<u64>,
+    /// The categories whose top-level directories `--no-reshuffle` leaves alone
+    sorted_dirs: Option<KnownCategories>,
+}
+
+impl FileFilter {
+    pub fn from_config(config: &SortConfig) -> Result<Self> {
+        let include = if config.include.is_empty() { None } else { Some(build_globset(&config.include)?) };
+        let extensions = if config.include_ext.is_empty() {
+            None
+        } else {
+            Some(config.include_ext.iter().map(|ext| ext.trim().trim_start_matches('.').to_lowercase()).collect())
+        };
+        Ok(FileFilter {
+            exclude: build_globset(&config.exclude)?,
+            include,
+            extensions,
+            older_than: config.older_than,
+            newer_than: config.newer_than,
+            min_size: config.min_size,
+            max_size: config.max_size,
+            sorted_dirs: config.no_reshuffle.then(|| KnownCategories::from_config(config)).transpose()?,
+        })
+    }
+
+    /// Whether the files in the top-level directory `name` count as sorted: with `--no-reshuffle`, if it's a
+    /// category the tool would choose without the model
+    pub fn is_sorted_dir(&self, name: &str) -> bool {
+        self.sorted_dirs.as_ref().is_some_and(|known| known.contains(name))
+    }
+
+    pub fn accepts(&self, name: &str) -> bool {
+        let extension_allowed = self.extensions.as_ref().is_none_or(|allowed| {
+            Path::new(name)
+                .extension()
+                .is_some_and(|ext| allowed.contains(&ext.to_string_lossy().to_lowercase()))
+        });
+
+        extension_allowed
+            && !self.exclude.is_match(name)
+            && self.include.as_ref().is_none_or(|include| include.is_match(name))
+    }
+
+    /// Whether the file at `path` was last modified within `--older-than` and `--newer-than`, and is within
+    /// `--min-size` and `--max-size`. Without any of these, its metadata isn't read; with one, a file whose
+    /// metadata can't be read is left alone.
+    pub fn accepts_metadata(&self, path: &Path) -> bool {
+        let by_time = self.older_than.is_some() || self.newer_than.is_some();
+        let by_size = self.min_size.is_some() || self.max_size.is_some();
+        if !by_time && !by_size {
+            return true;
+        }
+        let Ok(metadata) = fs::metadata(path) else { return false };
+        let size = metadata.len();
+        let size_fits = self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max);
+        size_fits && (!by_time || metadata.modified().is_ok_and(|modified| {
+            self.older_than.is_none_or(|cutoff| modified < cutoff) && self.newer_than.is_none_or(|cutoff| modified > cutoff)
+        }))
+    }
+}
+
+/// Categories the tool picks on its own rather than taking from the model: the built-in extension table, the
+/// fallback and special categories, those named by `--categories`, `--alias` and `--rules`, and the date or size
+/// categories of the modes that use them. A top-level directory named like one of them was likely made by an earlier
+/// run, even if the journal is gone, while an arbitrary folder like `dump` isn't taken for one.
+struct KnownCategories {
+    /// First levels of the category paths, lowercased, since `--category-case` may have changed them
+    names: HashSet<String>,
+    /// Formats of the date categories in use
+    date_formats: Vec<String>,
+}
+
+impl KnownCategories {
+    fn from_config(config: &SortConfig) -> Result<Self> {
+        let rules = config.rules.as_deref().map(Rules::load).transpose()?;
+        let builtin = Rules::builtin();
+        let aliases = config.alias.iter().map(|(_, to)| to.as_str());
+        let size_buckets = if config.by_size { size_bucket_names(&config.size_buckets) } else { &[] };
+        let policy = SanitizePolicy::from_config(config);
+        let names = [config.fallback_category.as_str(), DUPLICATES_CATEGORY, EMPTY_CATEGORY, MISC_CATEGORY].into_iter()
+            .chain(builtin.categories())
+            .chain(rules.iter().flat_map(Rules::categories))
+            .chain(config.categories.iter().map(String::as_str))
+            .chain(aliases)
+            .chain(size_buckets.iter().copied())
+            .map(|category| {
+                let sanitized = sanitize_category(category, &policy);
+                sanitized.split('/').next().unwrap_or_default().to_lowercase()
+            })
+            .collect();
+
+        let mut date_formats = Vec::new();
+        let custom = config.date_format.as_deref();
+        if config.by_exif_date {
+            date_formats.push(custom.unwrap_or(DEFAULT_EXIF_DATE_FORMAT).to_string());
+        }
+        if config.by_date {
+            date_formats.push(custom.unwrap_or(DEFAULT_MTIME_DATE_FORMAT).to_string());
+        }
+        Ok(KnownCategories { names, date_formats })
+    }
+
+    /// Whether the top-level directory `name` is named like one of the categories
+    fn contains(&self, name: &str) -> bool {
+        self.names.contains(&name.to_lowercase()) || self.date_formats.iter().any(|format| is_date_category(name, format))
+    }
+}
+
+/// The `.sortifyignore` files (and with `--respect-gitignore`, `.gitignore` files) of a directory and of those
+/// above it up to the target directory, outermost first
+#[derive(Clone)]
+struct IgnoreRules {
+    /// Names of the ignore files to read in each directory; patterns in later ones win
+    file_names: &'static [&'static str],
+    rules: Vec<Arc<Gitignore>>,
+}
+
+impl IgnoreRules {
+    /// The rules for the target directory `target_dir`
+    fn new(target_dir: &Path, config: &SortConfig) -> Self {
+        let file_names: &[&str] = if config.respect_gitignore { &[GITIGNORE_FILE, IGNORE_FILE] } else { &[IGNORE_FILE] };
+        IgnoreRules { file_names, rules: Vec::new() }.descend(target_dir)
+    }
+
+    /// The rules for `dir`, a subdirectory of the one these rules are for: these plus its own ignore files, if any.
+    /// An ignore file that can't be fully read is used as far as it could be, with a warning.
+    fn descend(&self, dir: &Path) -> IgnoreRules {
+        let paths: Vec<PathBuf> = self.file_names.iter().map(|name| dir.join(name)).filter(|path| path.is_file()).collect();
+        if paths.is_empty() {
+            return self.clone();
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        for path in &paths {
+            if let Some(e) = builder.add(path) {
+                warn!("Problem in {:?}: {}", path, e);
+            }
+        }
+        let mut rules = self.clone();
+        match builder.build() {
+            Ok(gitignore) => rules.rules.push(Arc::new(gitignore)),
+            Err(e) => warn!("Ignoring the ignore files in {:?}: {}", dir, e),
+        }
+        rules
+    }
+
+    /// Whether `path`, in the directory these rules are for, is ignored. As with git, the innermost ignore file
+    /// with a matching pattern decides, and the last such pattern in it, so `!keep.txt` can undo an outer `*.txt`.
+    fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules.iter().rev().find_map(|gitignore| match gitignore.matched(path, is_dir) {
+            Match::None => None,
+            Match::Ignore(_) => Some(true),
+            Match::Whitelist(_) => Some(false),
+        }) == Some(true)
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| SortError::InvalidConfig(format!("Invalid glob pattern '{}': {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| SortError::InvalidConfig(e.to_string()))
+}
+
+/// Absolute paths of the category directories that earlier runs moved files into
+pub fn category_dirs(target_dir: &Path) -> Result<HashSet<PathBuf>> {
+    Ok(Journal::read_entries(target_dir)?
+        .iter()
+        .filter_map(|e| e.to.parent().map(Path::to_path_buf))
+        .collect())
+}
+
+/// Collects the files to sort, descending into subdirectories in recursive mode.
+/// Category directories created by earlier runs are never descended into, so their files aren't re-sorted.
+/// Files and directories matched by a `.sortifyignore` in their directory or one above it are left out.
+pub fn collect_files(target_dir: &Path, config: &SortConfig) -> Result<Vec<PathBuf>> {
+    let categories = category_dirs(target_dir)?;
+    let filter = FileFilter::from_config(config)?;
+
+    let mut files = Vec::new();
+    let mut pending = vec![(target_dir.to_path_buf(), 0, IgnoreRules::new(target_dir, config))];
+    // Canonical paths of every directory queued so far, so followed symlinks can't cause cycles
+    let mut visited = HashSet::from([
+        fs::canonicalize(target_dir).map_err(SortError::io(format!("Failed to read directory {:?}", target_dir)))?,
+    ]);
+
+    while let Some((dir, depth, ignore)) = pending.pop() {
+        let read_error = || SortError::io(format!("Failed to read directory {:?}", dir));
+        // Only the target directory itself is needed; a subdirectory that can't be read is left out
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if depth > 0 => {
+                warn!("Skipping directory {:?}, which can't be read: {}", dir, e);
+                continue;
+            }
+            Err(e) => return Err(read_error()(e)),
+        };
+        for entry in entries {
+            let entry = entry.map_err(read_error())?;
+            let path = entry.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                warn_not_utf8(&path);
+                continue;
+            };
+            if is_tool_file(name, config) { continue; }
+
+            // Check link semantics explicitly rather than relying on `Path::is_dir` following links
+            let file_type = entry.file_type().map_err(read_error())?;
+            let is_dir = if file_type.is_symlink() {
+                fs::metadata(&path).is_ok_and(|m| m.is_dir())
+            } else {
+                file_type.is_dir()
+            };
+
+            let is_hidden = name.starts_with('.');
+            if ignore.ignores(&path, is_dir) { continue; }
+            if is_dir {
+                if is_hidden { continue; }
+                // Symlinked directories are only descended into when following symlinks
+                let may_descend = !file_type.is_symlink() || config.follow_symlinks;
+                let within_depth = config.max_depth.is_none_or(|max| depth < max);
+                let already_sorted = depth == 0 && filter.is_sorted_dir(name);
+                if config.recursive && may_descend && within_depth && !already_sorted
+                    && !categories.contains(&std::path::absolute(&path).map_err(read_error())?)
+                    && visited.insert(fs::canonicalize(&path).map_err(read_error())?)
+                {
+                    let ignore = ignore.descend(&path);
+                    pending.push((path, depth + 1, ignore));
+                }
+                continue;
+            }
+            if (!is_hidden || config.include_hidden) && filter.accepts(name) && filter.accepts_metadata(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Puts collected files in the order `--order` asks for. Ties, and files whose metadata can't be read
+/// (which go last), are ordered by path, so the order is the same from run to run.
+pub fn order_files(files: &mut [PathBuf], order: FileOrder) {
+    match order {
+        FileOrder::Name => files.sort(),
+        FileOrder::Mtime => files.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            (modified.is_none(), modified, path.clone())
+        }),
+        FileOrder::Size => files.sort_by_cached_key(|path| {
+            let size = fs::metadata(path).map(|m| m.len()).ok();
+            (size.is_none(), size, path.clone())
+        }),
+        FileOrder::None => {}
+    }
+}
+
+/// Decides whether a single file reported in watch mode should be sorted, by the rules `collect_files` applies
+/// while walking: it has to sit directly in `target_dir` (absolute) or, in recursive mode, within `--max-depth`
+/// below it, without passing through a hidden directory or a category directory, and not be ignored by a
+/// `.sortifyignore`.
+pub fn accepts_path(
+    path: &Path,
+    target_dir: &Path,
+    config: &SortConfig,
+    filter: &FileFilter,
+    categories: &HashSet<PathBuf>,
+) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        warn_not_utf8(path);
+        return false;
+    };
+    let is_hidden = name.starts_with('.');
+    if is_tool_file(name, config) || (is_hidden && !config.include_hidden) || !filter.accepts(name) {
+        return false;
+    }
+    // Like in `collect_files`, a link to a directory counts as a directory and any other link as a file
+    if fs::symlink_metadata(path).is_err() || fs::metadata(path).is_ok_and(|m| m.is_dir()) || !filter.accepts_metadata(path) {
+        return false;
+    }
+
+    let Some(relative) = path.parent().and_then(|parent| parent.strip_prefix(target_dir).ok()) else { return false };
+    let depth = relative.components().count();
+    if (depth > 0 && !config.recursive) || config.max_depth.is_some_and(|max| depth > max) {
+        return false;
+    }
+    let mut dir = target_dir.to_path_buf();
+    let mut ignore = IgnoreRules::new(target_dir, config);
+    let dirs_accepted = relative.components().enumerate().all(|(i, component)| {
+        dir.push(component);
+        let ignored = ignore.ignores(&dir, true);
+        ignore = ignore.descend(&dir);
+        let name = component.as_os_str().to_string_lossy();
+        let already_sorted = i == 0 && filter.is_sorted_dir(&name);
+        !name.starts_with('.') && !already_sorted && !categories.contains(&dir) && !ignored
+    });
+    dirs_accepted && !ignore.ignores(path, false)
+}
+
+/// Names that aren't valid UTF-8 can't be sent to the model, or matched against its answer, without being
+/// changed, so such files (and directories) are left where they are
+fn warn_not_utf8(path: &Path) {
+    warn!("Skipping {:?}: its name isn't valid UTF-8", path);
+}
+
+/// Whether `name` is a file the tool keeps, or one of the ignore files it reads, which are never sorted
+fn is_tool_file(name: &str, config: &SortConfig) -> bool {
+    TOOL_FILES.contains(&name) || (config.respect_gitignore && name == GITIGNORE_FILE)
+}
+
+/// Splits `files` into consecutive batches of at most `batch_size` files. With a `max_chars` budget, a batch
+/// also ends before its entries (as they appear in the prompt's JSON list) would exceed the budget,
+/// though a single file always gets a batch of its own even if its entry alone is over budget. With
+/// `by_extension`, a batch also ends where the extension changes; see [`group_by_extension`]. A batch always
+/// ends before a file whose name is already in it, as files from different subdirectories can share a name
+/// in recursive mode, and two of them in one prompt would only leave the model guessing which is which.
+/// `entry` gives the entry of each file in the prompt's list, its name along with any description, which is what
+/// counts against the budget; it's only called with a budget.
+pub fn build_batches(
+    files: &[PathBuf],
+    batch_size: usize,
+    max_chars: Option<usize>,
+    by_extension: bool,
+    entry: impl Fn(&Path) -> String,
+) -> Vec<&[PathBuf]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut chars = 0;
+    let mut names = HashSet::new();
+    for (i, path) in files.iter().enumerate() {
+        let name = file_name_string(path);
+        // The entry as a JSON string, and the comma separating it from the next
+        let cost = if max_chars.is_some() { json_len(&entry(path)) + 1 } else { 0 };
+        let count = i - start;
+        let over_budget = max_chars.is_some_and(|max_chars| chars + cost > max_chars);
+        let new_extension = by_extension && count > 0 && extension_key(path) != extension_key(&files[i - 1]);
+        if count > 0 && (count >= batch_size || over_budget || new_extension || names.contains(&name)) {
+            batches.push(&files[start..i]);
+            start = i;
+            chars = 0;
+            names.clear();
+        }
+        chars += cost;
+        names.insert(name);
+    }
+    if start < files.len() {
+        batches.push(&files[start..]);
+    }
+    batches
+}
+
+/// The length in characters of `text` as a JSON string, quotes and escapes included
+fn json_len(text: &str) -> usize {
+    serde_json::to_string(text).map_or(text.len(), |json| json.chars().count())
+}
+
+/// Reorders `files` so those with the same extension (`--group-by-ext`) are next to each other, keeping
+/// their order otherwise. Files without an extension form a group of their own.
+pub fn group_by_extension(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut grouped = files.to_vec();
+    grouped.sort_by_cached_key(|path| extension_key(path));
+    grouped
+}
+
+/// The extension a file is grouped by, ignoring case; empty if it has none
+fn extension_key(path: &Path) -> String {
+    path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(OsStr::from_bytes(b"bad\xff.txt")), "x").unwrap();
+        fs::write(dir.path().join("good.txt"), "x").unwrap();
+        let files = collect_files(dir.path(), &SortConfig::default()).unwrap();
+        assert_eq!(files, [dir.path().join("good.txt")]);
+    }
+
+    #[test]
+    fn prompt_budget_counts_the_whole_entry() {
+        let files: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"].iter().map(PathBuf::from).collect();
+        let described = |path: &Path| format!("{} (text: \"{}\")", path.display(), "x".repeat(20));
+        // Each entry is 40 characters as a JSON string, with its escaped quotes, and takes a comma
+        assert_eq!(build_batches(&files, 15, Some(90), false, described).len(), 2);
+        assert_eq!(build_batches(&files, 15, Some(90), false, |path| path.display().to_string()).len(), 1);
+    }
+
+    #[test]
+    fn no_reshuffle_only_leaves_category_folders_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        for folder in ["Music", "images", "Invoices", "2023-05", "Trips 2023", "dump"] {
+            fs::create_dir(dir.path().join(folder)).unwrap();
+            fs::write(dir.path().join(folder).join("file.dat"), "x").unwrap();
+        }
+        fs::write(dir.path().join("top.txt"), "x").unwrap();
+        let config = SortConfig {
+            recursive: true,
+            no_reshuffle: true,
+            by_date: true,
+            categories: vec!["Invoices".to_string()],
+            ..SortConfig::default()
+        };
+        let mut files = collect_files(dir.path(), &config).unwrap();
+        files.sort();
+        let expected = ["Trips 2023/file.dat", "dump/file.dat", "top.txt"].map(|file| dir.path().join(file));
+        assert_eq!(files, expected);
+    }
+}
@@ -0,0 +1,719 @@
+use crate::category::validate_replace_char;
+use crate::error::{Result, SortError};
+use crate::llm::KeepAlive;
+use crate::metadata::{validate_date_format, validate_size_buckets};
+use clap::{Parser, ValueEnum};
+use reqwest::header::{HeaderName, HeaderValue};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Everything that controls a sort run. Doubles as the CLI's option set, so every field is also a flag.
+#[derive(clap::Args, Debug, Clone)]
+#[command(about = None, long_about = None)]
+pub struct SortConfig {
+    /// The directory to sort
+    #[arg(short, long, default_value = ".", global = true)]
+    pub target_dir: String,
+
+    /// Put the category folders here instead of in the target directory (created if missing)
+    #[arg(long, value_name = "DIR")]
+    pub dest: Option<PathBuf>,
+
+    /// The LLM model to use, or a comma-separated list of models to fall back on in order, e.g. "qwen3:32b,qwen3:8b":
+    /// a batch moves on to the next model when the current one fails after its retries
+    #[arg(short, long, default_value = "gpt-oss:20b-cloud", value_parser = parse_models, global = true)]
+    pub model: String,
+
+    /// The API flavour spoken by the LLM server
+    #[arg(long, value_enum, default_value_t = BackendKind::Ollama, global = true)]
+    pub backend: BackendKind,
+
+    /// The LLM API URL [default: Ollama's /api/generate or /v1/chat/completions, depending on --backend]
+    #[arg(long, global = true)]
+    pub api_url: Option<String>,
+
+    /// API key sent as a bearer token, e.g. for a gateway or an authenticating proxy in front of Ollama
+    #[arg(long, env = "SORTIFY_API_KEY", hide_env_values = true, global = true)]
+    pub api_key: Option<String>,
+
+    /// How Ollama is told to produce JSON; use `json` for Ollama versions older than 0.5
+    #[arg(long, value_enum, default_value_t = FormatMode::Schema)]
+    pub format_mode: FormatMode,
+
+    /// File containing a custom prompt; `{filenames}` is replaced with the JSON list of filenames
+    #[arg(long)]
+    pub prompt_file: Option<PathBuf>,
+
+    /// Stream the model's answer chunk by chunk (ollama backend)
+    #[arg(long)]
+    pub stream: bool,
+
+    /// How long Ollama keeps the model loaded after each request, e.g. `5m`, `1h30m`, or `-1` for as long as it runs
+    #[arg(long, value_name = "DURATION", value_parser = parse_keep_alive, allow_hyphen_values = true)]
+    pub keep_alive: Option<KeepAlive>,
+
+    /// Sampling temperature; low values make the categories more predictable (ignored by servers without it)
+    #[arg(long)]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling: only consider the most likely tokens up to this probability mass, in (0, 1]
+    #[arg(long)]
+    pub top_p: Option<f32>,
+
+    /// Random seed, which together with --temperature 0 makes the answers repeatable (ignored by servers without it)
+    #[arg(long)]
+    pub seed: Option<i64>,
+
+    /// Number of files to process in a single LLM batch
+    #[arg(short, long, default_value = "15")]
+    pub batch_size: usize,
+
+    /// Character budget for the files in one prompt, with their types and snippets; batches are cut short before
+    /// exceeding it
+    #[arg(long)]
+    pub max_prompt_chars: Option<usize>,
+
+    /// Only batch files with the same extension together, so each prompt holds files of one kind
+    #[arg(long)]
+    pub group_by_ext: bool,
+
+    /// Show the model this many bytes from the start of text files, so vague names can be sorted by content (0 = off)
+    #[arg(long, default_value = "500")]
+    pub peek_bytes: usize,
+
+    /// Also show the model the start of the text on the first page of PDFs, within --peek-bytes
+    #[arg(long)]
+    pub read_pdf: bool,
+
+    /// Extra HTTP header for every request, e.g. "X-Tenant: 42" (repeatable)
+    #[arg(long, value_name = "KEY: VALUE", value_parser = parse_header, global = true)]
+    pub header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Send requests through this proxy, e.g. http://proxy.corp:3128 [default: from HTTP_PROXY/HTTPS_PROXY]
+    #[arg(long, value_name = "URL", conflicts_with = "no_proxy", global = true)]
+    pub proxy: Option<String>,
+
+    /// Ignore HTTP_PROXY/HTTPS_PROXY and connect to the LLM directly
+    #[arg(long, global = true)]
+    pub no_proxy: bool,
+
+    /// Trust this PEM (or DER) certificate as a root when connecting over HTTPS, e.g. a gateway's self-signed one
+    #[arg(long, value_name = "PATH", global = true)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Accept any TLS certificate, even invalid or self-signed ones. This makes HTTPS open to interception;
+    /// prefer --ca-cert
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Seconds to wait for the LLM to answer a request before it counts as a failed attempt
+    #[arg(long, default_value = "120", value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    pub timeout_secs: u64,
+
+    /// How often a failed batch is retried before its files are skipped (0 = a single attempt)
+    #[arg(long, default_value = "3")]
+    pub max_retries: u32,
+
+    /// Don't check that the server is up and has the model before sorting (ollama backend)
+    #[arg(long)]
+    pub skip_health_check: bool,
+
+    /// Pull the model without asking when the server doesn't have it yet (ollama backend)
+    #[arg(long, conflicts_with = "skip_health_check")]
+    pub auto_pull: bool,
+
+    /// Delay before retrying a failed batch, doubled with each further retry (plus random jitter)
+    #[arg(long, default_value = "1000")]
+    pub retry_base_ms: u64,
+
+    /// Upper bound for the delay between retries
+    #[arg(long, default_value = "30000")]
+    pub retry_max_ms: u64,
+
+    /// Maximum number of batches sent to the LLM at the same time
+    #[arg(short, long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub concurrency: u64,
+
+    /// Preview changes without moving files
+    #[arg(short = 'n', long, global = true)]
+    pub dry_run: bool,
+
+    /// Move files whose content is identical to an earlier file into a Duplicates folder
+    #[arg(long)]
+    pub dedup: bool,
+
+    /// Leave empty (zero-byte) files where they are, rather than moving them into an Empty folder
+    #[arg(long)]
+    pub skip_empty: bool,
+
+    /// Sort JPEG/HEIC photos into folders by their EXIF capture date instead of asking the LLM
+    #[arg(long)]
+    pub by_exif_date: bool,
+
+    /// Sort files into folders by their modification time, without asking the LLM at all
+    #[arg(long)]
+    pub by_date: bool,
+
+    /// Sort files into size buckets (Small, Medium, ...) without asking the LLM at all
+    #[arg(long, conflicts_with = "by_date")]
+    pub by_size: bool,
+
+    /// Ascending size thresholds separating the `--by-size` buckets (K/M/G/T suffixes, powers of 1024)
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', value_parser = parse_size, default_value = "1M,100M,1G")]
+    pub size_buckets: Vec<u64>,
+
+    /// strftime-style format for date categories; `/` creates nested folders
+    /// [default: %Y/%m for --by-exif-date, %Y-%m for --by-date]
+    #[arg(long)]
+    pub date_format: Option<String>,
+
+    /// Ask for confirmation before each file is moved
+    #[arg(short, long, conflicts_with = "json")]
+    pub interactive: bool,
+
+    /// Also record each run and its moves, with a hash of each file, in this SQLite database (created if missing)
+    #[arg(long, value_name = "PATH")]
+    pub history_db: Option<PathBuf>,
+
+    /// Review the proposed moves in a terminal UI before anything moves: leave files out, change their category,
+    /// then apply
+    #[arg(long, conflicts_with_all = ["json", "interactive", "watch", "plan"])]
+    pub tui: bool,
+
+    /// Whether files are moved into their category, or copied or linked there, leaving the originals in place
+    #[arg(long, value_enum, default_value_t = TransferMode::Move)]
+    pub mode: TransferMode,
+
+    /// Give copies the time they were made rather than the original's access and modification times
+    #[arg(long)]
+    pub no_preserve: bool,
+
+    /// Wait at least this long between two moves, however many batches run at once, to keep slow drives responsive
+    #[arg(long, value_name = "MS")]
+    pub throttle_ms: Option<u64>,
+
+    /// Move at most this many files per second, however many batches run at once
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_moves_per_sec: Option<u32>,
+
+    /// What to do when a file with the same name already exists in the category
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Rename, global = true)]
+    pub on_conflict: ConflictPolicy,
+
+    /// Also have the model suggest a cleaner name for each file, and rename it when moving
+    #[arg(long)]
+    pub rename: bool,
+
+    /// Ask the model how sure it is of each category and why; the reasons are logged with -v
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Put files the model is less sure of than this (0 to 1) in the fallback category instead; implies --explain
+    #[arg(long, value_name = "CONFIDENCE", value_parser = parse_fraction)]
+    pub min_confidence: Option<f64>,
+
+    /// Fold the smallest categories into Misc until at most this many remain; nothing moves until every batch is categorized
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_categories: Option<usize>,
+
+    /// Character to put in place of those a folder name can't contain, instead of dropping them
+    #[arg(long, value_name = "CHAR")]
+    pub replace_char: Option<char>,
+
+    /// Treat spaces in category names like characters a folder name can't contain
+    #[arg(long)]
+    pub no_spaces: bool,
+
+    /// Cut each level of a category name to this many characters
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_category_len: Option<usize>,
+
+    /// Use the category on the right whenever the model answers with the one on the left (any case), e.g. Pics=Images
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_alias)]
+    pub alias: Vec<(String, String)>,
+
+    /// Only ever use these categories, e.g. "Invoices,Receipts,Contracts": the model is told so, and other answers
+    /// become the closest of them, or the fallback category if none is close
+    #[arg(long, value_name = "LIST", value_delimiter = ',', value_parser = parse_category)]
+    pub categories: Vec<String>,
+
+    /// TOML or JSON file mapping extensions (`mp3`) or globs (`invoice*.pdf`) to categories, for files that
+    /// needn't go to the model; matching ignores case
+    #[arg(long, value_name = "FILE")]
+    pub rules: Option<PathBuf>,
+
+    /// Sort by file extension with a built-in table, extended by --rules, without contacting the model at all;
+    /// files of other types get the fallback category. Also used when the server can't be reached.
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Put a category into an existing folder of the destination whose name is close enough to it, e.g. Photos
+    /// for Photographs, rather than making a new folder; each such match is listed in the summary
+    #[arg(long)]
+    pub fuzzy_match: bool,
+
+    /// How similar, from 0 to 1, a category must be to an existing folder for --fuzzy-match to use it
+    #[arg(long, value_name = "SIMILARITY", value_parser = parse_fraction, default_value = "0.85", requires = "fuzzy_match")]
+    pub fuzzy_threshold: f64,
+
+    /// Show the model a few of the files in each existing category folder of the destination, so new files
+    /// are sorted by the same conventions
+    #[arg(long)]
+    pub learn: bool,
+
+    /// Casing of category folder names; names differing only in case always share a folder
+    #[arg(long, value_enum, default_value_t = CategoryCase::AsIs)]
+    pub category_case: CategoryCase,
+
+    /// Keep every category a single directory, even when the model answers with a path like `Media/Music`
+    #[arg(long)]
+    pub flat: bool,
+
+    /// Category for files the model leaves out of its answer
+    #[arg(long, default_value = "Unsorted")]
+    pub fallback_category: String,
+
+    /// Retry a batch when the model answers with filenames that weren't asked about, instead of ignoring them
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Neither read nor update the cache of earlier model answers
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Delete the cache of earlier model answers before sorting
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// Work out every move, but write them to this JSON file for review instead of moving anything (`apply` carries them out)
+    #[arg(long, value_name = "FILE", conflicts_with = "watch")]
+    pub plan: Option<PathBuf>,
+
+    /// Keep running and sort new files as they appear in the target directory, until interrupted with Ctrl-C
+    #[arg(long)]
+    pub watch: bool,
+
+    /// How long a new file must go unchanged before watch mode sorts it, in milliseconds
+    #[arg(long, default_value = "2000", requires = "watch")]
+    pub debounce_ms: u64,
+
+    /// Print a JSON report of every move on stdout when done, instead of a line per file; other output goes to stderr
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Don't show a progress bar (useful when piping output to a log)
+    #[arg(long)]
+    pub no_progress: bool,
+
+    /// Time each request to the model, and sum up the timings at the end and in the --json report;
+    /// pair with --dry-run to tune --batch-size and --concurrency without moving anything
+    #[arg(long)]
+    pub bench: bool,
+
+    /// Also sort files found in subdirectories of the target directory
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Also sort hidden files (dotfiles). Hidden directories are still never descended into
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// Leave files alone that a .gitignore in the target directory or below ignores, like build output in a
+    /// project checkout
+    #[arg(long)]
+    pub respect_gitignore: bool,
+
+    /// Descend into symlinked directories and copy the targets of symlinked files,
+    /// instead of leaving linked directories alone and handling links as plain files
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Skip files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only sort files whose name matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Only sort files with one of these extensions, e.g. `mp3,flac,wav` (case-insensitive)
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    pub include_ext: Vec<String>,
+
+    /// Only sort files last modified before this: a time ago like `30d`, `12h` or `1y`, or a date like `2024-01-31`
+    #[arg(long, value_name = "AGE|DATE", value_parser = parse_cutoff)]
+    pub older_than: Option<SystemTime>,
+
+    /// Only sort files last modified after this: a time ago like `30d`, `12h` or `1y`, or a date like `2024-01-31`
+    #[arg(long, value_name = "AGE|DATE", value_parser = parse_cutoff)]
+    pub newer_than: Option<SystemTime>,
+
+    /// Only sort files of at least this size, e.g. `500K` or `1.5G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Only sort files of at most this size, e.g. `500K` or `1.5G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only sort the first N of the files found, in --order, e.g. to try out settings with --dry-run
+    #[arg(long, value_name = "N", conflicts_with = "watch")]
+    pub limit: Option<usize>,
+
+    /// Order to sort the files in, which decides which of them share a batch and which --limit takes
+    #[arg(long, value_enum, default_value_t = FileOrder::Name)]
+    pub order: FileOrder,
+
+    /// In recursive mode, leave files alone that sit in a top-level directory named like a category the tool picks
+    /// without the model (built-in, --categories, --alias, --rules, dates or sizes), even if no earlier run
+    /// recorded it as one
+    #[arg(long, requires = "recursive")]
+    pub no_reshuffle: bool,
+
+    /// How many directory levels below the target to descend in recursive mode
+    /// (0 = top-level files only; unlimited by default)
+    #[arg(long, requires = "recursive")]
+    pub max_depth: Option<usize>,
+
+    /// Remove the subdirectories that moving files out of left empty
+    #[arg(long, requires = "recursive")]
+    pub prune_empty: bool,
+}
+
+impl Default for SortConfig {
+    /// The same defaults the CLI uses when no flags are given
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct DefaultsOnly {
+            #[command(flatten)]
+            config: SortConfig,
+        }
+        DefaultsOnly::parse_from(["sortify"]).config
+    }
+}
+
+impl SortConfig {
+    /// The models of `--model`, in the order they're tried
+    pub fn models(&self) -> Vec<&str> {
+        self.model.split(',').collect()
+    }
+
+    /// Whether the model is asked for its confidence and reasons, by `--explain` or as `--min-confidence` needs them
+    pub fn explains(&self) -> bool {
+        self.explain || self.min_confidence.is_some()
+    }
+
+    /// The directory the category folders go in: `--dest`, or else the target directory
+    pub fn destination(&self) -> &Path {
+        self.dest.as_deref().unwrap_or(Path::new(&self.target_dir))
+    }
+
+    /// The least time between two moves, from `--throttle-ms` and `--max-moves-per-sec`, whichever is longer
+    pub fn move_interval(&self) -> Option<Duration> {
+        let throttle = self.throttle_ms.map(Duration::from_millis);
+        let rate = self.max_moves_per_sec.map(|per_sec| Duration::from_secs(1) / per_sec);
+        throttle.max(rate)
+    }
+
+    /// Checks the options that can't be validated while parsing them, without touching any files
+    pub fn validate(&self) -> Result<()> {
+        let target_path = Path::new(&self.target_dir);
+        if !target_path.is_dir() {
+            return Err(SortError::TargetNotADirectory(target_path.to_path_buf()));
+        }
+        if let Some(dest) = self.dest.as_ref().filter(|dest| dest.exists() && !dest.is_dir()) {
+            return Err(SortError::InvalidConfig(format!("Destination {:?} is not a directory", dest)));
+        }
+        if self.mode == TransferMode::Hardlink {
+            validate_same_filesystem(target_path, self.destination())?;
+        }
+        if let Some(format) = &self.date_format {
+            validate_date_format(format)?;
+        }
+        if let Some(url) = &self.api_url {
+            let invalid = |reason: String| SortError::InvalidConfig(format!("Invalid API URL '{}': {}", url, reason));
+            let parsed = reqwest::Url::parse(url).map_err(|e| invalid(e.to_string()))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(invalid("expected an http:// or https:// URL, e.g. http://localhost:11434/api/generate".to_string()));
+            }
+        }
+        if let Some(proxy) = &self.proxy {
+            reqwest::Url::parse(proxy).map_err(|e| SortError::InvalidConfig(format!("Invalid proxy '{}': {}", proxy, e)))?;
+        }
+        if let Some(temperature) = self.temperature.filter(|t| t.is_nan() || *t < 0.0) {
+            return Err(SortError::InvalidConfig(format!("--temperature must not be negative, got {}", temperature)));
+        }
+        if let Some(top_p) = self.top_p.filter(|p| p.is_nan() || *p <= 0.0 || *p > 1.0) {
+            return Err(SortError::InvalidConfig(format!("--top-p must be in (0, 1], got {}", top_p)));
+        }
+        if let Some(c) = self.replace_char {
+            validate_replace_char(c)?;
+        }
+        if let (Some(older_than), Some(newer_than)) = (self.older_than, self.newer_than) {
+            if older_than <= newer_than {
+                return Err(SortError::InvalidConfig("--older-than and --newer-than match no files together; the --newer-than cutoff has to come first".to_string()));
+            }
+        }
+        if let (Some(min_size), Some(max_size)) = (self.min_size, self.max_size) {
+            if min_size > max_size {
+                return Err(SortError::InvalidConfig(format!("--min-size ({} bytes) is larger than --max-size ({} bytes)", min_size, max_size)));
+            }
+        }
+        validate_size_buckets(&self.size_buckets)
+    }
+}
+
+/// Checks that hard links from the target directory into `dest` (or the closest of its parents that exists) are
+/// possible, so that `--mode hardlink` fails up front rather than for every file
+#[cfg(unix)]
+fn validate_same_filesystem(target_dir: &Path, dest: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let device = |path: &Path| std::fs::metadata(path).map(|m| m.dev()).ok();
+    let dest_device = dest.ancestors().find_map(device);
+    if device(target_dir).is_some_and(|target| dest_device.is_some_and(|dest| dest != target)) {
+        return Err(SortError::InvalidConfig(format!(
+            "--mode hardlink needs {:?} on the same filesystem as the target directory; use --mode symlink or --mode copy instead",
+            dest
+        )));
+    }
+    Ok(())
+}
+
+/// Elsewhere, a failed link is reported file by file
+#[cfg(not(unix))]
+fn validate_same_filesystem(_target_dir: &Path, _dest: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Ollama's native generate API
+    Ollama,
+    /// Any OpenAI-compatible chat completions API
+    #[value(name = "openai")]
+    OpenAi,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatMode {
+    /// Send a JSON schema for a filename -> category object
+    Schema,
+    /// Plain JSON mode
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileOrder {
+    /// By path, so files in the same directory stay together
+    Name,
+    /// Oldest modification time first
+    Mtime,
+    /// Smallest first
+    Size,
+    /// As the file system lists them, which may change from run to run
+    None,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Move the file into its category
+    Move,
+    /// Put a copy into the category, leaving the original in place
+    Copy,
+    /// Put a symbolic link to the original into the category, leaving the original in place
+    Symlink,
+    /// Put a hard link to the original into the category, leaving the original in place; only works within
+    /// one filesystem
+    Hardlink,
+}
+
+impl TransferMode {
+    /// Whether the original stays where it is, so undoing only has to remove what was placed in the category
+    pub fn keeps_source(self) -> bool {
+        self != TransferMode::Move
+    }
+
+    pub fn imperative(self) -> &'static str {
+        match self {
+            TransferMode::Move => "Move",
+            TransferMode::Copy => "Copy",
+            TransferMode::Symlink => "Link",
+            TransferMode::Hardlink => "Hard-link",
+        }
+    }
+
+    pub fn infinitive(self) -> &'static str {
+        match self {
+            TransferMode::Move => "move",
+            TransferMode::Copy => "copy",
+            TransferMode::Symlink => "link",
+            TransferMode::Hardlink => "hard-link",
+        }
+    }
+
+    pub fn progressive(self) -> &'static str {
+        match self {
+            TransferMode::Move => "Moving",
+            TransferMode::Copy => "Copying",
+            TransferMode::Symlink => "Linking",
+            TransferMode::Hardlink => "Hard-linking",
+        }
+    }
+
+    pub fn past(self) -> &'static str {
+        match self {
+            TransferMode::Move => "Moved",
+            TransferMode::Copy => "Copied",
+            TransferMode::Symlink => "Linked",
+            TransferMode::Hardlink => "Hard-linked",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep both files, appending a numeric suffix like `file (1).txt` to the new one
+    Rename,
+    /// Leave the source file where it is
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Leave the source file where it is if the existing file has the same contents, and otherwise rename like
+    /// `rename`, so re-runs don't make copies of files already sorted
+    HashSkip,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CategoryCase {
+    /// Keep the model's casing, e.g. `Tax Returns`
+    AsIs,
+    /// Capitalize each word, e.g. `Tax Returns` from `tax returns`
+    Title,
+    /// Lowercase everything, e.g. `tax returns`
+    Lower,
+}
+
+/// Parses a `Key: Value` HTTP header. Values are marked sensitive, as they often carry credentials.
+fn parse_header(input: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = input.split_once(':').ok_or_else(|| format!("expected 'Key: Value', got '{}'", input))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| format!("invalid header name '{}'", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim()).map_err(|_| format!("invalid value for header '{}'", name))?;
+    value.set_sensitive(true);
+    Ok((name, value))
+}
+
+/// Parses an Ollama keep-alive: a number of seconds, or a Go duration such as `30s`, `5m` or `1h30m`.
+/// A negative value keeps the model loaded indefinitely.
+fn parse_keep_alive(input: &str) -> Result<KeepAlive, String> {
+    let input = input.trim();
+    if let Ok(seconds) = input.parse::<i64>() {
+        return Ok(KeepAlive::Seconds(seconds));
+    }
+
+    let invalid = || format!("invalid duration '{}', expected e.g. 300, 5m, 1h30m or -1", input);
+    let mut rest = input.strip_prefix(['-', '+']).unwrap_or(input);
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        if number.parse::<f64>().is_err() {
+            return Err(invalid());
+        }
+        let unit = ["ns", "us", "µs", "ms", "s", "m", "h"].into_iter()
+            .filter(|unit| tail.starts_with(unit))
+            .max_by_key(|unit| unit.len())
+            .ok_or_else(invalid)?;
+        rest = &tail[unit.len()..];
+    }
+    Ok(KeepAlive::Duration(input.to_string()))
+}
+
+/// Parses the `--model` list, tidying the spaces around its entries
+fn parse_models(input: &str) -> Result<String, String> {
+    let models: Vec<&str> = input.split(',').map(str::trim).collect();
+    if models.contains(&"") {
+        return Err("empty model name in the list".to_string());
+    }
+    Ok(models.join(","))
+}
+
+/// Parses one entry of the `--categories` list
+fn parse_category(input: &str) -> Result<String, String> {
+    match input.trim() {
+        "" => Err("empty category in the list".to_string()),
+        category => Ok(category.to_string()),
+    }
+}
+
+/// Parses a number between 0 and 1, such as a similarity or confidence
+fn parse_fraction(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(similarity) if (0.0..=1.0).contains(&similarity) => Ok(similarity),
+        Ok(_) => Err("must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses a modification time cutoff: an age like `90m`, `12h`, `30d`, `2w` or `1y` before now, or a local date
+/// (`2024-01-31`, meaning its start) or RFC 3339 time (`2024-01-31T12:00:00Z`)
+fn parse_cutoff(input: &str) -> Result<SystemTime, String> {
+    let input = input.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(input) {
+        return Ok(time.into());
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+        return midnight.and_local_timezone(chrono::Local).earliest()
+            .map(SystemTime::from)
+            .ok_or_else(|| format!("{} doesn't exist in the local time zone", input));
+    }
+
+    let invalid = || format!("invalid age or date '{}', expected e.g. 30d, 12h, 1y or 2024-01-31", input);
+    let split = input.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (number, unit) = input.split_at(split);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    number.checked_mul(unit_secs)
+        .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)))
+        .ok_or_else(|| format!("age '{}' is too long", input))
+}
+
+/// Parses a `FROM=TO` category alias
+fn parse_alias(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("expected FROM=TO, got '{}'", input)),
+    }
+}
+
+/// Parses a human-readable size like `512`, `10K`, `1.5M` or `2GiB` into bytes (powers of 1024)
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let split = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", input))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{}' (expected K, M, G or T)", input)),
+    };
+    let bytes = number * multiplier as f64;
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size '{}' is too large", input));
+    }
+    Ok(bytes as u64)
+}
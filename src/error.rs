@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+
+/// Result type of the library, failing with a [`SortError`]
+pub type Result<T, E = SortError> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while categorizing or sorting files
+#[derive(Debug, thiserror::Error)]
+pub enum SortError {
+    #[error("Target directory does not exist or is not a directory: {0:?}")]
+    TargetNotADirectory(PathBuf),
+
+    /// An option or input file is unusable, e.g. an invalid glob or date format
+    #[error("{0}")]
+    InvalidConfig(String),
+
+    /// The request never got an HTTP response
+    #[error("Network Error")]
+    LlmUnreachable(#[source] reqwest::Error),
+
+    /// The server couldn't be reached by the health check before the run
+    #[error("Cannot reach the LLM server at {url}; is it running, and is --api-url right?")]
+    ServerUnavailable {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// The server doesn't have the configured model. Retrying won't help, so this ends the run.
+    #[error("Model '{model}' is not available on the server{}; pull it with `ollama pull {model}` or choose another with --model", list_models(available))]
+    ModelNotFound {
+        model: String,
+        /// The models the server does have, if it said
+        available: Vec<String>,
+    },
+
+    /// The server turned the request away for now (HTTP 429); it's worth trying again after `retry_after`, if it said
+    #[error("Rate limited by the LLM server: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<std::time::Duration>,
+    },
+
+    /// The server answered, but with an error
+    #[error("API Error: {0}")]
+    Api(String),
+
+    /// The model's answer (or the response around it) isn't the expected JSON
+    #[error("JSON Parse Error. Response was: {response}")]
+    ResponseParse {
+        response: String,
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// The model's answer is well-formed but unusable, e.g. it names files that weren't asked about
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Failed to transfer '{from}' to '{to}'")]
+    MoveFailed {
+        from: PathBuf,
+        to: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to watch the target directory")]
+    Watch(#[source] notify::Error),
+
+    /// The run couldn't be recorded in the `--history-db` database
+    #[error("Failed to record the run in the history database {path:?}")]
+    History {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("Undo journal is corrupt")]
+    JournalCorrupt(#[source] serde_json::Error),
+
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl SortError {
+    /// Whether the error will happen again however often the request is retried, so the run should stop
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, SortError::ModelNotFound { .. })
+    }
+
+    /// Wraps an I/O error with a description of what was being done, for use with `map_err`
+    pub(crate) fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> SortError {
+        let context = context.into();
+        move |source| SortError::Io { context, source }
+    }
+}
+
+fn list_models(models: &[String]) -> String {
+    if models.is_empty() {
+        String::new()
+    } else {
+        format!(" (it has: {})", models.join(", "))
+    }
+}
+
+/// Formats an error followed by its chain of causes, the way anyhow's `{:#}` does
+pub(crate) fn display_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
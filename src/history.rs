@@ -0,0 +1,82 @@
+use crate::config::SortConfig;
+use crate::error::{display_chain, Result, SortError};
+use crate::metadata::hash_file;
+use crate::report::SortReport;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use tracing::warn;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        timestamp TEXT NOT NULL,
+        target_dir TEXT NOT NULL,
+        dest_dir TEXT,
+        model TEXT NOT NULL,
+        mode TEXT NOT NULL,
+        files_found INTEGER NOT NULL,
+        moved INTEGER NOT NULL,
+        skipped INTEGER NOT NULL,
+        failed INTEGER NOT NULL,
+        interrupted INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS moves (
+        run_id INTEGER NOT NULL REFERENCES runs(id),
+        source TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        category TEXT NOT NULL,
+        hash TEXT
+    );
+    CREATE INDEX IF NOT EXISTS moves_run_id ON moves(run_id);
+";
+
+/// Records the run in the history database of `config.history_db`, if set, warning rather than failing if that
+/// doesn't work. Dry runs did nothing worth recording.
+pub fn record_history(config: &SortConfig, report: &SortReport) {
+    let Some(db) = &config.history_db else { return };
+    if config.dry_run {
+        return;
+    }
+    if let Err(e) = record_run(db, config, report) {
+        warn!("{}", display_chain(&e));
+    }
+}
+
+/// Adds a finished run and each of its moves, with the BLAKE3 hash of the moved file, to the SQLite database
+/// at `db`, creating it if needed
+fn record_run(db: &Path, config: &SortConfig, report: &SortReport) -> Result<()> {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string();
+    let record = || -> rusqlite::Result<()> {
+        let mut connection = Connection::open(db)?;
+        connection.execute_batch(SCHEMA)?;
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO runs (timestamp, target_dir, dest_dir, model, mode, files_found, moved, skipped, failed, interrupted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                chrono::Utc::now().to_rfc3339(),
+                absolute(Path::new(&config.target_dir)),
+                config.dest.as_deref().map(absolute),
+                config.model,
+                config.mode.infinitive(),
+                report.files_found as i64,
+                report.moved as i64,
+                report.skipped as i64,
+                report.failed as i64,
+                report.interrupted,
+            ],
+        )?;
+        let run_id = transaction.last_insert_rowid();
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO moves (run_id, source, destination, category, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for file in &report.files {
+                let hash = hash_file(&file.to).ok().map(|hash| hash.to_hex().to_string());
+                insert.execute(params![run_id, absolute(&file.from), absolute(&file.to), file.category, hash])?;
+            }
+        }
+        transaction.commit()
+    };
+    record().map_err(|source| SortError::History { path: db.to_path_buf(), source })
+}
@@ -0,0 +1,167 @@
+use crate::config::TransferMode;
+use crate::error::{Result, SortError};
+use crate::report::UndoReport;
+use crate::transfer::move_file;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Name of the undo journal kept inside the target directory
+pub const JOURNAL_FILE: &str = ".sortify-journal.jsonl";
+
+/// A single completed move, as recorded in the undo journal
+#[derive(Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub run: u128,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// The source was copied or linked rather than moved, so undoing just removes `to`
+    #[serde(default)]
+    pub copied: bool,
+    /// Where the category folders were created, if not in the target directory (`--dest`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<PathBuf>,
+}
+
+/// Appends completed moves of the current run to the journal file
+pub struct Journal {
+    path: PathBuf,
+    run: u128,
+    dest: Option<PathBuf>,
+}
+
+impl Journal {
+    /// The journal of a new run over `target_dir`, whose category folders go in `dest` if given
+    pub fn new(target_dir: &Path, dest: Option<&Path>) -> Self {
+        let run = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+        let dest = dest.map(|dest| std::path::absolute(dest).unwrap_or_else(|_| dest.to_path_buf()));
+        Journal { path: target_dir.join(JOURNAL_FILE), run, dest }
+    }
+
+    pub fn record(&self, from: &Path, to: &Path, mode: TransferMode) -> Result<()> {
+        let append = || -> io::Result<()> {
+            let entry = JournalEntry {
+                run: self.run,
+                from: std::path::absolute(from)?,
+                to: std::path::absolute(to)?,
+                copied: mode.keeps_source(),
+                dest: self.dest.clone(),
+            };
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&entry)?)
+        };
+        append().map_err(SortError::io("Failed to write undo journal"))
+    }
+
+    /// Reads every entry of the journal in `target_dir`, or none if there is no journal yet
+    pub fn read_entries(target_dir: &Path) -> Result<Vec<JournalEntry>> {
+        let journal_path = target_dir.join(JOURNAL_FILE);
+        if !journal_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&journal_path).map_err(SortError::io("Failed to read undo journal"))?;
+        contents.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<JournalEntry>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(SortError::JournalCorrupt)
+    }
+}
+
+/// Reverses every move of the most recent run recorded in the journal, newest first, and reports how many were
+/// reverted. Moves that fail to revert are left in the journal for another try.
+pub fn undo(target_dir: &Path) -> Result<UndoReport> {
+    if !target_dir.is_dir() {
+        return Err(SortError::TargetNotADirectory(target_dir.to_path_buf()));
+    }
+
+    let journal_path = target_dir.join(JOURNAL_FILE);
+    if !journal_path.exists() {
+        return Ok(UndoReport::default());
+    }
+
+    let entries = Journal::read_entries(target_dir)?;
+    let Some(last_run) = entries.iter().map(|e| e.run).max() else {
+        return Ok(UndoReport { journal_found: true, ..UndoReport::default() });
+    };
+    let (to_undo, mut remaining): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.run == last_run);
+    let files_found = to_undo.len();
+
+    let mut restored = 0;
+    let mut category_dirs = HashSet::new();
+    // Moves that couldn't be reverted stay in the journal, so undo can be run again once what's in the way is gone
+    let mut kept = Vec::new();
+    for entry in to_undo.into_iter().rev() {
+        // A link whose file has gone since is still there to restore
+        if fs::symlink_metadata(&entry.to).is_err() {
+            warn!("'{}' no longer exists, skipping", entry.to.display());
+            continue;
+        }
+        if let Some(parent) = entry.to.parent() {
+            category_dirs.insert((parent.to_path_buf(), entry.dest.clone()));
+        }
+        if entry.copied {
+            match fs::remove_file(&entry.to) {
+                Ok(()) => {
+                    info!("Removing '{}', whose original stayed in place", entry.to.display());
+                    restored += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to remove '{}': {}", entry.to.display(), e);
+                    kept.push(entry);
+                }
+            }
+            continue;
+        }
+        if fs::symlink_metadata(&entry.from).is_ok() {
+            warn!("'{}' already exists, not overwriting", entry.from.display());
+            kept.push(entry);
+            continue;
+        }
+        if let Some(parent) = entry.from.parent() {
+            fs::create_dir_all(parent).map_err(SortError::io("Failed to recreate source directory"))?;
+        }
+        match move_file(&entry.to, &entry.from) {
+            Ok(()) => {
+                info!("Restoring '{}' -> '{}'", entry.to.display(), entry.from.display());
+                restored += 1;
+            }
+            Err(e) => {
+                warn!("Failed to restore '{}': {}", entry.to.display(), e);
+                kept.push(entry);
+            }
+        }
+    }
+    remaining.extend(kept.into_iter().rev());
+
+    // Only succeeds for directories that are now empty. Nested categories are removed up to the target directory,
+    // or the destination they were created in.
+    let target_dir = std::path::absolute(target_dir).map_err(SortError::io("Failed to resolve target directory"))?;
+    for (dir, dest) in category_dirs {
+        let root = dest.as_deref().unwrap_or(&target_dir);
+        let mut dir = dir.as_path();
+        while dir.starts_with(root) && dir != root && fs::remove_dir(dir).is_ok() {
+            info!("Removed empty category '{}'", dir.display());
+            let Some(parent) = dir.parent() else { break };
+            dir = parent;
+        }
+    }
+
+    if remaining.is_empty() {
+        fs::remove_file(&journal_path).map_err(SortError::io("Failed to remove undo journal"))?;
+    } else {
+        let lines = remaining.iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io::Error::from)
+            .map_err(SortError::io("Failed to rewrite undo journal"))?;
+        fs::write(&journal_path, lines.join("\n") + "\n").map_err(SortError::io("Failed to rewrite undo journal"))?;
+    }
+
+    Ok(UndoReport { journal_found: true, files_found, reverted: restored })
+}
@@ -0,0 +1,92 @@
+use crate::category::existing_categories;
+use crate::error::{display_chain, Result, SortError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Name of the file inside the target directory that keeps the examples `--learn` found
+pub const LEARNED_FILE: &str = ".sortify-learned.json";
+
+/// How many files of each category folder are shown to the model
+const EXAMPLES_PER_CATEGORY: usize = 3;
+
+/// Filenames from the category folders of a destination, to show the model how its files have been sorted
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LearnedExamples {
+    /// When each category folder was last modified as of the scan, in milliseconds since the epoch. A folder that
+    /// gained or lost files since has a different time, which makes the examples stale.
+    folders: BTreeMap<String, u64>,
+    /// A few filenames per category folder, leaving out empty folders
+    pub examples: BTreeMap<String, Vec<String>>,
+}
+
+impl LearnedExamples {
+    /// The examples from the category folders in `dest`, as kept in `target_dir` if the folders haven't changed
+    /// since, and otherwise scanned afresh and, if `keep`, kept for next time. Failing to keep them only costs a
+    /// rescan.
+    pub fn load(target_dir: &Path, dest: &Path, keep: bool) -> Self {
+        let path = target_dir.join(LEARNED_FILE);
+        let folders = folder_times(dest);
+        let kept: Option<LearnedExamples> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+        if let Some(kept) = kept.filter(|kept| kept.folders == folders) {
+            return kept;
+        }
+
+        let examples = folders.keys()
+            .filter_map(|category| {
+                let files = sample_files(&dest.join(category));
+                (!files.is_empty()).then(|| (category.clone(), files))
+            })
+            .collect();
+        let learned = LearnedExamples { folders, examples };
+        if !keep {
+            return learned;
+        }
+        if let Err(e) = learned.save(&path) {
+            warn!("{}", display_chain(&e));
+        }
+        learned
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string(self).map_err(io::Error::from).map_err(SortError::io("Failed to write learned examples"))?;
+        fs::write(path, contents).map_err(SortError::io("Failed to write learned examples"))
+    }
+}
+
+/// The category folders of `dest` with their modification times
+fn folder_times(dest: &Path) -> BTreeMap<String, u64> {
+    existing_categories(dest)
+        .into_iter()
+        .map(|name| {
+            let modified = fs::metadata(dest.join(&name))
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |age| age.as_millis() as u64);
+            (name, modified)
+        })
+        .collect()
+}
+
+/// Up to [`EXAMPLES_PER_CATEGORY`] names of the files directly in `dir`, spread evenly over them by name so that
+/// the same folder always gives the same examples
+fn sample_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    let step = names.len().div_ceil(EXAMPLES_PER_CATEGORY).max(1);
+    names.into_iter().step_by(step).take(EXAMPLES_PER_CATEGORY).collect()
+}
@@ -0,0 +1,101 @@
+//! Sorts the files of a directory into category folders chosen by a language model.
+//!
+//! [`sort_directory`] performs a whole run the way the `llm_sorter` binary does, while [`categorize`]
+//! only asks the model about a list of filenames and leaves the files alone.
+
+mod cache;
+mod category;
+mod collect;
+mod config;
+mod error;
+mod history;
+mod journal;
+mod learn;
+pub mod llm;
+mod metadata;
+mod place;
+mod plan;
+mod report;
+mod rules;
+mod sort;
+mod style;
+mod transfer;
+mod unsort;
+mod watch;
+
+pub use config::{parse_size, BackendKind, CategoryCase, ConflictPolicy, FileOrder, FormatMode, SortConfig, TransferMode};
+pub use error::{Result, SortError};
+pub use journal::undo;
+pub use place::{MOVES_TARGET, TERMINAL_TARGET};
+pub use plan::{Plan, PlannedMove};
+pub use report::{FileMove, RequestTiming, SortReport, UndoReport, UnsortReport};
+pub use category::{sanitize_category, CategoryPaths, ResolvedCategory, SanitizePolicy};
+pub use unsort::unsort;
+pub use watch::watch_directory;
+
+use indicatif::ProgressBar;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+
+/// Asks the model configured in `config` for a category for each of `filenames`, retrying as a sort run would,
+/// and fails if the model couldn't answer for some of them.
+/// Returns the categories exactly as the model gave them; see [`sanitize_category`] to turn them into paths.
+pub async fn categorize(client: &Client, config: &SortConfig, filenames: &[String]) -> Result<HashMap<String, String>> {
+    let backend = sort::build_backend(client.clone(), config)?;
+    let retry = sort::RetryPolicy::from_config(config);
+    let answers = sort::query_llm(backend.as_ref(), &ProgressBar::hidden(), filenames, filenames, config.strict, &retry).await?;
+    if let Some((_, e)) = answers.failed.into_iter().next() {
+        return Err(e);
+    }
+    Ok(answers.answers.into_iter().map(|(filename, answer)| (filename, answer.category)).collect())
+}
+
+/// Makes sure the server configured in `config` is up and has the model, so a run fails fast rather than after
+/// the first batch has used up its retries
+pub async fn check_server(config: &SortConfig) -> Result<()> {
+    sort::build_backend(sort::build_client(config)?, config)?.health_check().await
+}
+
+/// Lists the models available on the server configured in `config`
+pub async fn list_models(config: &SortConfig) -> Result<Vec<llm::ModelInfo>> {
+    sort::build_backend(sort::build_client(config)?, config)?.list_models().await
+}
+
+/// Has the server download the model configured in `config`, passing each progress update to `on_progress`
+pub async fn pull_model(config: &SortConfig, on_progress: &(dyn Fn(&llm::PullProgress) + Send + Sync)) -> Result<()> {
+    sort::build_backend(sort::build_client(config)?, config)?.pull_model(on_progress).await
+}
+
+/// Sorts the files in `config.target_dir` into category directories, as configured, stopping at the next file
+/// once `interrupted` completes (see [`SortReport::interrupted`]).
+/// With `config.plan` set, nothing is moved and the moves are written to that file as a [`Plan`] instead,
+/// unless the run is interrupted. A dry run prints a line on stdout for each file it would move if `preview` is
+/// set, as the binary does unless `--quiet` or `--json` is given.
+pub async fn sort_directory(config: &SortConfig, preview: bool, interrupted: impl Future<Output = ()>) -> Result<SortReport> {
+    config.validate()?;
+    let target_path = Path::new(&config.target_dir);
+    let models = sort::build_backends(sort::build_client(config)?, config)?;
+
+    let mut files = collect::collect_files(target_path, config)?;
+    collect::order_files(&mut files, config.order);
+    if let Some(limit) = config.limit {
+        files.truncate(limit);
+    }
+    let Some(plan_path) = &config.plan else {
+        let report = sort::sort_files(config, &models, &files, preview, interrupted).await?;
+        history::record_history(config, &report);
+        return Ok(report);
+    };
+
+    // Planning is a dry run whose moves are kept
+    let dry_config = SortConfig { dry_run: true, ..config.clone() };
+    let report = sort::sort_files(&dry_config, &models, &files, preview, interrupted).await?;
+    // A plan missing some of the files would look complete
+    if report.interrupted {
+        return Ok(report);
+    }
+    Plan::from_report(&report, target_path, config.dest.as_deref(), &config.model).save(plan_path)?;
+    Ok(report)
+}
@@ -0,0 +1,667 @@
+use crate::error::{Result, SortError};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tracing::debug;
+
+const SYSTEM_PROMPT: &str = "You are a file organization assistant. You only ever answer with a JSON object.";
+
+/// Placeholder in a prompt template that is replaced with the JSON list of filenames
+pub const FILENAMES_PLACEHOLDER: &str = "{filenames}";
+
+const DEFAULT_PROMPT: &str = "Analyze this list of filenames and assign a concise directory name for each.
+        Rules:
+        1. Group files primarily by file extension and type (e.g., all .mp3/.wav files should go to 'Music' or 'Audio', .jpg/.png to 'Images').
+        2. Do NOT translate Japanese or foreign filenames to English for the category name. Classify them by their file type (e.g. 'Music').
+        3. Use specific categories only if semantically distinct (e.g., 'Invoices' vs 'Documents').
+        4. Some filenames are followed by details in parentheses: their detected content type (e.g. 'IMG001.dat (image/png)') or the start of their text (e.g. 'untitled.txt (text: \"Invoice #1042 ...\")'). Use them to choose the category, but key the output by the filename alone.
+        5. The filenames are given as a JSON list. Key the output by each filename exactly as it is in the list, with any quotes or backslashes in it escaped the same way.
+        Return ONLY a JSON object mapping filenames to directory names.
+        Filenames: {filenames}
+        Example output: { \"song.mp3\": \"Music\", \"photo.jpg\": \"Images\", \"invoice.pdf\": \"Documents\" }";
+
+/// The default prompt for `--rename`, which also asks for a better filename
+const DEFAULT_RENAME_PROMPT: &str = "Analyze this list of filenames, assign a concise directory name for each and suggest a clean, descriptive filename.
+        Rules:
+        1. Group files primarily by file extension and type (e.g., all .mp3/.wav files should go to 'Music' or 'Audio', .jpg/.png to 'Images').
+        2. Do NOT translate Japanese or foreign filenames to English for the category name or the new filename. Classify them by their file type (e.g. 'Music').
+        3. Use specific categories only if semantically distinct (e.g., 'Invoices' vs 'Documents').
+        4. Some filenames are followed by details in parentheses: their detected content type (e.g. 'IMG001.dat (image/png)') or the start of their text (e.g. 'untitled.txt (text: \"Invoice #1042 ...\")'). Use them to choose the category and filename, but key the output by the filename alone.
+        5. Keep each file's extension. If a filename is already clear, suggest it unchanged.
+        6. The filenames are given as a JSON list. Key the output by each filename exactly as it is in the list, with any quotes or backslashes in it escaped the same way.
+        Return ONLY a JSON object mapping each filename to an object with its \"category\" and new \"filename\".
+        Filenames: {filenames}
+        Example output: { \"scan0001.pdf\": { \"category\": \"Invoices\", \"filename\": \"Acme invoice 2024-03.pdf\" }, \"song.mp3\": { \"category\": \"Music\", \"filename\": \"song.mp3\" } }";
+
+/// A language model that can assign a directory name to each of a list of filenames
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Makes a single request asking to categorize `filenames`, returning the model's answer as it gave it
+    async fn ask(&self, filenames: &[String]) -> Result<String>;
+
+    /// Makes a single attempt at categorizing `filenames`, returning a filename -> answer map
+    async fn categorize(&self, filenames: &[String]) -> Result<HashMap<String, FileAnswer>> {
+        parse_mapping(&self.ask(filenames).await?)
+    }
+
+    /// Checks that the server is up and serves the configured model, before any batch is sent.
+    /// Backends that have no way of telling just succeed.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lists the models the server has available
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Err(SortError::InvalidConfig("Only the ollama backend can list models".to_string()))
+    }
+
+    /// Downloads the configured model onto the server, reporting each progress update to `on_progress`
+    async fn pull_model(&self, _on_progress: &(dyn for<'p> Fn(&'p PullProgress) + Send + Sync)) -> Result<()> {
+        Err(SortError::InvalidConfig("Only the ollama backend can pull models".to_string()))
+    }
+}
+
+/// A model the server has available
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub name: String,
+    /// Size on disk in bytes
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// A progress update while the server downloads a model
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullProgress {
+    /// What the server is doing, e.g. `pulling manifest` or `verifying sha256 digest`
+    pub status: String,
+    /// Bytes downloaded so far of the current layer
+    pub completed: Option<u64>,
+    /// Size of the current layer in bytes
+    pub total: Option<u64>,
+}
+
+/// The model's answer for a single file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileAnswer {
+    pub category: String,
+    /// A better name for the file, if the model was asked for one (`--rename`)
+    pub filename: Option<String>,
+    /// How sure the model is of the category, from 0 to 1, if it was asked (`--explain`)
+    pub confidence: Option<f64>,
+    /// Why the model chose the category, if it was asked (`--explain`)
+    pub reason: Option<String>,
+}
+
+/// A value of the model's answer object: a bare category, or a category with a filename, confidence and reason
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnswerValue {
+    Category(String),
+    Detailed {
+        category: String,
+        #[serde(default)]
+        filename: Option<String>,
+        #[serde(default)]
+        confidence: Option<f64>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+}
+
+/// An element of an answer given as an array rather than an object
+#[derive(Deserialize)]
+struct AnswerRecord {
+    filename: String,
+    category: String,
+    /// The suggested name (`--rename`)
+    #[serde(default)]
+    new_filename: Option<String>,
+    #[serde(default)]
+    confidence: Option<f64>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl AnswerRecord {
+    fn into_entry(self) -> (String, FileAnswer) {
+        let AnswerRecord { filename, category, new_filename, confidence, reason } = self;
+        (filename, FileAnswer { category, filename: new_filename, confidence, reason })
+    }
+}
+
+impl From<AnswerValue> for FileAnswer {
+    fn from(value: AnswerValue) -> Self {
+        match value {
+            AnswerValue::Category(category) => FileAnswer { category, ..FileAnswer::default() },
+            AnswerValue::Detailed { category, filename, confidence, reason } => FileAnswer { category, filename, confidence, reason },
+        }
+    }
+}
+
+/// The categorization prompt, with a `{filenames}` placeholder for the batch
+#[derive(Clone)]
+pub struct PromptTemplate(String);
+
+impl PromptTemplate {
+    pub fn new(template: String) -> Result<Self> {
+        if !template.contains(FILENAMES_PLACEHOLDER) {
+            return Err(SortError::InvalidConfig(format!(
+                "Prompt template must contain the {} placeholder",
+                FILENAMES_PLACEHOLDER
+            )));
+        }
+        // Quotes around the list would turn it into a string with unescaped quotes of its own inside
+        if ["\"", "'"].iter().any(|quote| template.contains(&format!("{quote}{FILENAMES_PLACEHOLDER}{quote}"))) {
+            return Err(SortError::InvalidConfig(format!(
+                "The {} placeholder in the prompt template must not be quoted, it's replaced with a JSON list",
+                FILENAMES_PLACEHOLDER
+            )));
+        }
+        Ok(PromptTemplate(template))
+    }
+
+    /// Builds the categorization prompt for a batch of filenames
+    pub fn render(&self, filenames: &[String]) -> String {
+        self.0.replace(FILENAMES_PLACEHOLDER, &prompt_json(filenames))
+    }
+}
+
+/// `value` as JSON to put in a prompt, with the `{filenames}` placeholder escaped wherever a name contains it, so
+/// that a file or directory named like it can't have the batch substituted into it
+fn prompt_json<T: Serialize + ?Sized>(value: &T) -> String {
+    // Only ever inside a string, where `\u007b` is just another way of writing `{`
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace(FILENAMES_PLACEHOLDER, &FILENAMES_PLACEHOLDER.replacen('{', "\\u007b", 1))
+}
+
+impl PromptTemplate {
+    /// The built-in prompt asking for a new filename along with each category
+    pub fn renaming() -> Self {
+        PromptTemplate(DEFAULT_RENAME_PROMPT.to_string())
+    }
+
+    /// The prompt with a closing rule to reuse the given existing `categories` where they fit
+    pub fn preferring(self, categories: &[String]) -> Self {
+        if categories.is_empty() {
+            return self;
+        }
+        let list = prompt_json(categories);
+        PromptTemplate(format!(
+            "{}\n        These directories already exist; use their exact names wherever they fit, rather than a new name for the same thing: {}",
+            self.0, list
+        ))
+    }
+
+    /// The prompt with a closing rule to sort files the way the given `examples`, filenames by the directory
+    /// they were sorted into, have been (`--learn`)
+    pub fn learning_from(self, examples: &BTreeMap<String, Vec<String>>) -> Self {
+        if examples.is_empty() {
+            return self;
+        }
+        let list = prompt_json(examples);
+        PromptTemplate(format!(
+            "{}\n        These files were already sorted into these directories; put similar files where they would go \
+            by the same conventions: {}",
+            self.0, list
+        ))
+    }
+
+    /// The prompt with a closing rule asking for the model's confidence in each category and its reason (`--explain`)
+    pub fn explaining(self) -> Self {
+        PromptTemplate(format!(
+            "{}\n        For each file, also give your \"confidence\" in its category from 0.0 to 1.0 and a short \"reason\" for it, \
+            so each value is an object like {{ \"category\": \"Music\", \"confidence\": 0.9, \"reason\": \"MP3 audio file\" }}, \
+            keeping any other fields asked for above.",
+            self.0
+        ))
+    }
+
+    /// The prompt with a closing rule that the filenames come with the subdirectories they're in, which key the
+    /// output while the category is still chosen by the filename (`--recursive`)
+    pub fn with_paths(self) -> Self {
+        PromptTemplate(format!(
+            "{}\n        Files in subdirectories are listed with their path, like \"Trips/2023/IMG_0001.jpg\". Choose the category \
+            by the filename after the last slash as you would otherwise, but key the output by the whole path exactly as listed, and leave the path out of any new filename.",
+            self.0
+        ))
+    }
+
+    /// The prompt with a closing rule that only the given `categories` may be used (`--categories`)
+    pub fn restricted_to(self, categories: &[String]) -> Self {
+        if categories.is_empty() {
+            return self;
+        }
+        let list = prompt_json(categories);
+        PromptTemplate(format!("{}\n        Only ever use one of these directory names: {}", self.0, list))
+    }
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        PromptTemplate(DEFAULT_PROMPT.to_string())
+    }
+}
+
+/// Sampling parameters sent along with each request, where set. Servers that don't know one ignore it.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Sampling {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+impl Sampling {
+    fn is_default(&self) -> bool {
+        self.temperature.is_none() && self.top_p.is_none() && self.seed.is_none()
+    }
+}
+
+/// How long Ollama keeps a model loaded after a request: a number of seconds, or a duration like `5m`.
+/// Negative values keep it loaded indefinitely.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum KeepAlive {
+    Seconds(i64),
+    Duration(String),
+}
+
+/// Talks to Ollama's native `/api/generate` endpoint
+pub struct OllamaBackend {
+    pub client: Client,
+    pub api_url: String,
+    pub model: String,
+    pub prompt: PromptTemplate,
+    /// Receive the answer as newline-delimited JSON chunks instead of one response
+    pub stream: bool,
+    /// Constrain the answer with a JSON schema rather than plain JSON mode (needs Ollama 0.5+)
+    pub json_schema: bool,
+    /// Have the schema ask for a filename along with each category (`--rename`)
+    pub rename: bool,
+    /// Have the schema ask for a confidence and reason along with each category (`--explain`)
+    pub explain: bool,
+    /// The only categories the schema allows, if restricted (`--categories`)
+    pub categories: Vec<String>,
+    /// Bearer token for a proxy in front of Ollama
+    pub api_key: Option<String>,
+    pub sampling: Sampling,
+    /// How long the server keeps the model loaded between requests, if not its own default
+    pub keep_alive: Option<KeepAlive>,
+}
+
+/// Talks to any server implementing OpenAI's `/v1/chat/completions` endpoint (vLLM, llama.cpp, Ollama, ...)
+pub struct OpenAiBackend {
+    pub client: Client,
+    pub api_url: String,
+    pub model: String,
+    pub prompt: PromptTemplate,
+    pub api_key: Option<String>,
+    pub sampling: Sampling,
+}
+
+#[derive(Serialize)]
+struct OllamaRequest {
+    model: String,
+    prompt: String,
+    stream: bool,
+    /// Either the string `"json"` or a JSON schema the answer must satisfy
+    format: serde_json::Value,
+    #[serde(skip_serializing_if = "Sampling::is_default")]
+    options: Sampling,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<KeepAlive>,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    response: String,
+}
+
+/// The answer of Ollama's `/api/tags`, listing the models that have been pulled
+#[derive(Deserialize)]
+struct OllamaTags {
+    models: Vec<ModelInfo>,
+}
+
+/// One line of Ollama's streamed `/api/pull` answer
+#[derive(Deserialize)]
+struct OllamaPullChunk {
+    #[serde(flatten)]
+    progress: Option<PullProgress>,
+    error: Option<String>,
+}
+
+/// One line of a streamed Ollama response
+#[derive(Deserialize)]
+struct OllamaChunk {
+    #[serde(default)]
+    response: String,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage>,
+    #[serde(flatten)]
+    sampling: Sampling,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    async fn ask(&self, filenames: &[String]) -> Result<String> {
+        let request = OllamaRequest {
+            model: self.model.clone(),
+            prompt: self.prompt.render(filenames),
+            stream: self.stream,
+            format: if self.json_schema { mapping_schema(self.rename, self.explain, &self.categories) } else { serde_json::json!("json") },
+            options: self.sampling,
+            keep_alive: self.keep_alive.clone(),
+        };
+
+        let mut builder = self.client.post(&self.api_url).json(&request);
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+
+        let response = builder.send().await.map_err(SortError::LlmUnreachable)?;
+        let response = check_status(response, &self.model).await?;
+        let text = if self.stream {
+            read_stream(response).await?
+        } else {
+            parse_body::<OllamaResponse>(response).await?.response
+        };
+        debug!(response = %text, "Raw model answer");
+        Ok(text)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let names: Vec<String> = self.list_models().await?.into_iter().map(|model| model.name).collect();
+        if names.iter().any(|name| same_model(name, &self.model)) {
+            Ok(())
+        } else {
+            Err(SortError::ModelNotFound { model: self.model.clone(), available: names })
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let url = self.endpoint("/api/tags")?;
+        let mut builder = self.client.get(url.clone());
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+        let response = builder.send().await
+            .map_err(|source| SortError::ServerUnavailable { url: url.to_string(), source })?;
+        let tags: OllamaTags = parse_body(check_status(response, &self.model).await?).await?;
+        Ok(tags.models)
+    }
+
+    async fn pull_model(&self, on_progress: &(dyn for<'p> Fn(&'p PullProgress) + Send + Sync)) -> Result<()> {
+        let url = self.endpoint("/api/pull")?;
+        // A download can take far longer than the timeout meant for a categorization request
+        let mut builder = self.client.post(url.clone())
+            .json(&serde_json::json!({ "model": self.model, "stream": true }))
+            .timeout(Duration::from_secs(24 * 60 * 60));
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+        let response = builder.send().await
+            .map_err(|source| SortError::ServerUnavailable { url: url.to_string(), source })?;
+        let mut response = check_status(response, &self.model).await?;
+
+        let mut buffer = Vec::new();
+        while let Some(bytes) = response.chunk().await.map_err(SortError::LlmUnreachable)? {
+            buffer.extend_from_slice(&bytes);
+            while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                let chunk: OllamaPullChunk = serde_json::from_slice(&line).map_err(|error| SortError::ResponseParse {
+                    response: String::from_utf8_lossy(&line).into_owned(),
+                    error,
+                })?;
+                if let Some(error) = chunk.error {
+                    return Err(SortError::Api(error));
+                }
+                if let Some(progress) = chunk.progress {
+                    on_progress(&progress);
+                    if progress.status == "success" {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Err(SortError::InvalidResponse(format!("pull of model '{}' ended before it succeeded", self.model)))
+    }
+}
+
+impl OllamaBackend {
+    /// Another endpoint of the Ollama server behind `api_url`, e.g. `/api/tags`
+    fn endpoint(&self, path: &str) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.api_url)
+            .map_err(|e| SortError::InvalidConfig(format!("Invalid API URL '{}': {}", self.api_url, e)))?;
+        url.set_path(path);
+        url.set_query(None);
+        Ok(url)
+    }
+}
+
+/// Whether two Ollama model names are the same, where a name without a tag means `:latest`
+fn same_model(a: &str, b: &str) -> bool {
+    let tagged = |name: &str| if name.contains(':') { name.to_string() } else { format!("{}:latest", name) };
+    tagged(a) == tagged(b)
+}
+
+/// Concatenates the `response` fields of a streamed Ollama answer until the final `done` chunk
+async fn read_stream(mut response: reqwest::Response) -> Result<String> {
+    let mut buffer = Vec::new();
+    let mut text = String::new();
+
+    while let Some(bytes) = response.chunk().await.map_err(SortError::LlmUnreachable)? {
+        buffer.extend_from_slice(&bytes);
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let chunk: OllamaChunk = serde_json::from_slice(&line).map_err(|error| SortError::ResponseParse {
+                response: String::from_utf8_lossy(&line).into_owned(),
+                error,
+            })?;
+            if let Some(error) = chunk.error {
+                return Err(SortError::Api(error));
+            }
+            text.push_str(&chunk.response);
+            if chunk.done {
+                return Ok(text);
+            }
+        }
+    }
+
+    Err(SortError::InvalidResponse("stream ended before the response was done".to_string()))
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn ask(&self, filenames: &[String]) -> Result<String> {
+        let request = ChatRequest {
+            model: &self.model,
+            messages: vec![
+                ChatMessage { role: "system".to_string(), content: SYSTEM_PROMPT.to_string() },
+                ChatMessage { role: "user".to_string(), content: self.prompt.render(filenames) },
+            ],
+            sampling: self.sampling,
+        };
+
+        let mut builder = self.client.post(&self.api_url).json(&request);
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+
+        let response = builder.send().await.map_err(SortError::LlmUnreachable)?;
+        let response = check_status(response, &self.model).await?;
+        let chat_res = parse_body::<ChatResponse>(response).await?;
+        let choice = chat_res.choices.into_iter().next()
+            .ok_or_else(|| SortError::InvalidResponse("response contained no choices".to_string()))?;
+        debug!(response = %choice.message.content, "Raw model answer");
+        Ok(choice.message.content)
+    }
+}
+
+/// Schema for an object mapping filenames to category names, or with `rename` or `explain` to an object holding
+/// the category and a new filename, or confidence and reason, respectively. Unless `categories` is empty, the
+/// category must be one of them.
+fn mapping_schema(rename: bool, explain: bool, categories: &[String]) -> serde_json::Value {
+    let category = if categories.is_empty() {
+        serde_json::json!({ "type": "string" })
+    } else {
+        serde_json::json!({ "type": "string", "enum": categories })
+    };
+    if !rename && !explain {
+        return serde_json::json!({ "type": "object", "additionalProperties": category });
+    }
+
+    let mut properties = serde_json::json!({ "category": category });
+    let mut required = vec!["category"];
+    if rename {
+        properties["filename"] = serde_json::json!({ "type": "string" });
+        required.push("filename");
+    }
+    if explain {
+        properties["confidence"] = serde_json::json!({ "type": "number", "minimum": 0, "maximum": 1 });
+        properties["reason"] = serde_json::json!({ "type": "string" });
+        required.extend(["confidence", "reason"]);
+    }
+    serde_json::json!({
+        "type": "object",
+        "additionalProperties": { "type": "object", "properties": properties, "required": required },
+    })
+}
+
+/// Turns an unsuccessful response into an error. A 404 about the model means `model` doesn't exist on the server,
+/// which no retry will fix, while a 429 says when to try again.
+async fn check_status(response: reqwest::Response, model: &str) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let retry_after = response.headers().get(reqwest::header::RETRY_AFTER).and_then(|value| value.to_str().ok()).and_then(parse_retry_after);
+    let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+    let lower = error_text.to_lowercase();
+    if status == reqwest::StatusCode::NOT_FOUND && lower.contains("model") && (lower.contains("not found") || lower.contains("model_not_found")) {
+        return Err(SortError::ModelNotFound { model: model.to_string(), available: Vec::new() });
+    }
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(SortError::RateLimited { message: error_text, retry_after });
+    }
+    Err(SortError::Api(format!("{} - {}", status, error_text)))
+}
+
+/// Parses a `Retry-After` header: a number of seconds, or an HTTP date to wait until
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    // A date in the past means right away
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Reads the whole response body and parses it as JSON, keeping the raw body for the error message
+async fn parse_body<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let body = response.text().await.map_err(SortError::LlmUnreachable)?;
+    serde_json::from_str(&body).map_err(|error| SortError::ResponseParse { response: body, error })
+}
+
+/// Parses the model's answer into a filename -> answer map, tolerating markdown code fences. Each value may be
+/// a bare category or an object with a `category` and an optional `filename`, `confidence` and `reason`.
+/// Models that answer with an array of `{"filename", "category"}` records instead are understood as well.
+pub fn parse_mapping(response: &str) -> Result<HashMap<String, FileAnswer>> {
+    let clean_json = response.trim();
+    let clean_json = clean_json.strip_prefix("```json").unwrap_or(clean_json);
+    let clean_json = clean_json.strip_prefix("```").unwrap_or(clean_json);
+    let clean_json = clean_json.strip_suffix("```").unwrap_or(clean_json);
+
+    match serde_json::from_str::<HashMap<String, AnswerValue>>(clean_json) {
+        Ok(answers) => Ok(answers.into_iter().map(|(filename, value)| (filename, value.into())).collect()),
+        // The error about the object form is the one worth reporting, as that's what was asked for
+        Err(error) => match serde_json::from_str::<Vec<AnswerRecord>>(clean_json) {
+            Ok(records) => Ok(records.into_iter().map(AnswerRecord::into_entry).collect()),
+            Err(_) => Err(SortError::ResponseParse { response: response.to_string(), error }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADVERSARIAL: &[&str] = &["a\"b.mp3", "back\\slash.pdf", "c{d}e.jpg", "{filenames}.txt", "it's\n.txt", "}{\"]["];
+
+    /// The JSON list that follows `marker` on its own line of `prompt`
+    fn list_after(prompt: &str, marker: &str) -> Vec<String> {
+        let line = prompt.lines().find_map(|line| line.trim().strip_prefix(marker)).expect("marker in prompt");
+        serde_json::from_str(line).expect("valid JSON list")
+    }
+
+    #[test]
+    fn render_injects_adversarial_names_as_a_json_list() {
+        let names: Vec<String> = ADVERSARIAL.iter().map(|name| name.to_string()).collect();
+        let prompt = PromptTemplate::new("Filenames: {filenames}\nDone".to_string()).unwrap().render(&names);
+        assert_eq!(list_after(&prompt, "Filenames: "), names);
+        assert!(prompt.ends_with("\nDone"));
+    }
+
+    #[test]
+    fn render_leaves_placeholders_in_suffix_lists_alone() {
+        let existing = vec!["{filenames}".to_string()];
+        let prompt = PromptTemplate::new("Filenames: {filenames}".to_string()).unwrap()
+            .restricted_to(&existing)
+            .render(&["song.mp3".to_string()]);
+        assert_eq!(list_after(&prompt, "Filenames: "), ["song.mp3"]);
+        assert_eq!(list_after(&prompt, "Only ever use one of these directory names: "), existing);
+    }
+
+    #[test]
+    fn quoted_placeholder_is_rejected() {
+        assert!(PromptTemplate::new("Filenames: \"{filenames}\"".to_string()).is_err());
+        assert!(PromptTemplate::new("Filenames: '{filenames}'".to_string()).is_err());
+        assert!(PromptTemplate::new("Filenames: {filenames}".to_string()).is_ok());
+    }
+
+    #[test]
+    fn default_prompts_render_as_valid_lists() {
+        let names: Vec<String> = ADVERSARIAL.iter().map(|name| name.to_string()).collect();
+        for template in [PromptTemplate::default(), PromptTemplate::renaming()] {
+            assert_eq!(list_after(&template.render(&names), "Filenames: "), names);
+        }
+    }
+}
@@ -1,193 +1,670 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use llm_sorter::{
+    check_server, list_models, pull_model, sort_directory, undo, unsort, watch_directory, BackendKind, Plan, RequestTiming,
+    SortConfig, SortError, SortReport, UndoReport, MOVES_TARGET, TERMINAL_TARGET,
+};
+use owo_colors::{OwoColorize, Stream, Style};
+use std::ffi::OsString;
+use std::future::Future;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+mod tui;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The directory to sort
-    #[arg(short, long, default_value = ".")]
-    target_dir: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Config file providing defaults for any of these options [default: ~/.config/sortify/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
 
-    /// The LLM model to use
-    #[arg(short, long, default_value = "gpt-oss:20b-cloud")]
-    model: String,
+    /// Show each move (-v), or also request timings and raw model answers (-vv)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
 
-    /// The Ollama API URL
-    #[arg(long, default_value = "http://localhost:11434/api/generate")]
-    api_url: String,
+    /// Only show errors, not warnings or the summary
+    #[arg(short, long, global = true)]
+    quiet: bool,
 
-    /// Number of files to process in a single LLM batch
-    #[arg(short, long, default_value = "15")]
-    batch_size: usize,
+    /// Also append timestamped log lines to this file, including every move even without -v
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
 
-    /// Preview changes without moving files
-    #[arg(short, long)]
-    dry_run: bool,
+    /// Without a subcommand, the options of `sort` apply
+    #[command(flatten)]
+    sort: SortArgs,
 }
 
-#[derive(Serialize)]
-struct OllamaRequest {
-    model: String,
-    prompt: String,
-    stream: bool,
-    format: String,
+/// The directories of a sort run, and how to sort them
+#[derive(clap::Args, Debug, Clone)]
+#[command(about = None, long_about = None)]
+struct SortArgs {
+    /// Directories to sort, each on its own, instead of --target-dir
+    #[arg(value_name = "DIR", conflicts_with = "target_dir")]
+    dirs: Vec<String>,
+
+    #[command(flatten)]
+    config: SortConfig,
 }
 
-#[derive(Deserialize)]
-struct OllamaResponse {
-    response: String,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Sort the files of the target directory into category folders (the default)
+    Sort(SortArgs),
+    /// Revert the most recent sort run recorded in the target directory's journal
+    Undo,
+    /// Move every file out of the target directory's subfolders back into it and remove the emptied folders,
+    /// for when there is no journal to undo with
+    Unsort,
+    /// List the models available on the server, with their sizes
+    Models,
+    /// Carry out the moves of the plan file given with --plan, without asking the model
+    Apply(SortConfig),
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-    let client = Client::new();
-    let target_path = Path::new(&args.target_dir);
+    let args = parse_args()?;
+    init_logging(&args)?;
+    // Options shared by every subcommand (--target-dir, --api-url, ...) are global, so they're here too
+    let shared = &args.sort.config;
+    if shared.insecure {
+        tracing::warn!("--insecure: TLS certificates are NOT checked, so anyone on the network path can read and alter the requests");
+    }
 
-    if !target_path.exists() || !target_path.is_dir() {
-        anyhow::bail!("Target directory does not exist or is not a directory: {:?}", target_path);
+    match &args.command {
+        None => sort(&args.sort, args.quiet).await,
+        Some(Command::Sort(sort_args)) => sort(sort_args, args.quiet).await,
+        Some(Command::Undo) => undo_last_run(shared, args.quiet),
+        Some(Command::Unsort) => flatten(shared, args.quiet),
+        Some(Command::Models) => models(shared).await,
+        Some(Command::Apply(config)) => apply(config, args.quiet).await,
     }
+}
+
+/// Prints a progress line of the run. With --json, stdout carries nothing but the report.
+fn say(config: &SortConfig, quiet: bool, line: String) {
+    match (quiet, config.json) {
+        (true, _) => {}
+        (false, true) => eprintln!("{}", line),
+        (false, false) => println!("{}", line),
+    }
+}
+
+/// Sorts each of the directories on its own, with categories of its own
+async fn sort(args: &SortArgs, quiet: bool) -> Result<()> {
+    let config = &args.config;
+    let say = |line: String| say(config, quiet, line);
 
-    if args.dry_run {
-        println!("*** DRY RUN: No files will be moved ***");
+    let mut configs: Vec<SortConfig> = match args.dirs.as_slice() {
+        [] => vec![config.clone()],
+        dirs => dirs.iter().map(|dir| SortConfig { target_dir: dir.clone(), ..config.clone() }).collect(),
+    };
+    if configs.len() > 1 && (config.watch || config.plan.is_some() || config.tui) {
+        anyhow::bail!("--watch, --plan and --tui work on a single directory");
+    }
+    if config.tui && !(io::stdin().is_terminal() && io::stdout().is_terminal()) {
+        anyhow::bail!("--tui needs a terminal");
+    }
+    for config in &configs {
+        config.validate()?;
+    }
+    warn_unusual_api_url(config);
+    if !config.skip_health_check && !config.offline {
+        match check_server(config).await {
+            Err(SortError::ModelNotFound { model, .. }) if config.auto_pull || confirm_pull(&model)? => {
+                pull(config, quiet).await?;
+            }
+            // A rough sort beats none, and undo is there if it's not wanted
+            Err(e @ SortError::ServerUnavailable { .. }) => {
+                tracing::warn!("{:#}; sorting by file extension instead, as with --offline", anyhow::Error::new(e));
+                for config in &mut configs {
+                    config.offline = true;
+                }
+            }
+            result => result?,
+        }
     }
 
-    println!("Sorting files in {:?} using model '{}' (Batch size: {})...", target_path, args.model, args.batch_size);
+    if let Some(plan) = &config.plan {
+        say(format!("*** PLAN: No files will be moved, the moves are written to {:?} ***", plan));
+    } else if config.dry_run {
+        say("*** DRY RUN: No files will be moved ***".to_string());
+    }
 
-    let entries = fs::read_dir(target_path).context("Failed to read directory")?;
-    let mut files_to_process = Vec::new();
+    let mut reports = Vec::new();
+    for config in &configs {
+        let target_path = Path::new(&config.target_dir);
+        if config.offline {
+            say(format!("Sorting files in {:?} by file extension, without a model (--offline)...", target_path));
+        } else {
+            let models = config.models().join("', then '");
+            say(format!("Sorting files in {:?} using model '{}' (Batch size: {})...", target_path, models, config.batch_size));
+        }
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() { continue; }
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if name.starts_with('.') { continue; }
-            files_to_process.push(path);
+        // Ctrl-C ends a watch gracefully and stops a run at the next file; if the handler can't be installed, it
+        // just ends the process
+        let interrupted = async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        let report = if config.watch {
+            say("Watching for new files, press Ctrl-C to stop...".to_string());
+            watch_directory(config, !quiet, interrupted).await?
+        } else if config.tui {
+            review(config, interrupted, quiet).await?
+        } else {
+            sort_directory(config, !quiet, interrupted).await?
+        };
+        let interrupted = report.interrupted;
+        reports.push(report);
+        if interrupted {
+            break;
         }
     }
 
-    if files_to_process.is_empty() {
-        println!("No files found to sort.");
+    match reports.as_slice() {
+        [report] => finish(config, report, quiet)?,
+        _ => finish_all(&configs, &reports, quiet)?,
+    }
+    if let Some(report) = reports.last().filter(|report| report.interrupted) {
+        let config = &configs[reports.len() - 1];
+        offer_rollback(config, report)?;
+        std::process::exit(130);
+    }
+    Ok(())
+}
+
+/// Warns if --api-url doesn't point at the endpoint the backend talks to, a common mistake that otherwise only
+/// shows as failing requests
+fn warn_unusual_api_url(config: &SortConfig) {
+    let Some(url) = config.api_url.as_deref().and_then(|url| reqwest::Url::parse(url).ok()) else { return };
+    let expected = match config.backend {
+        BackendKind::Ollama => "/api/generate",
+        BackendKind::OpenAi => "/chat/completions",
+    };
+    if !url.path().trim_end_matches('/').ends_with(expected) {
+        tracing::warn!(
+            "--api-url {} doesn't end in {}, the endpoint of the {} backend; is the path right?",
+            url,
+            expected,
+            config.backend.to_possible_value().map_or_else(String::new, |value| value.get_name().to_string()),
+        );
+    }
+}
+
+/// After an interrupted run, offers to undo the moves it made so far. Only asks on a terminal, and not with
+/// --json, where stdout is the report.
+fn offer_rollback(config: &SortConfig, report: &SortReport) -> Result<()> {
+    let moved_anything = report.moved > 0 && !config.dry_run && config.plan.is_none();
+    if !moved_anything || config.json {
         return Ok(());
     }
+    let question = format!("Undo the {} moves made so far?", report.moved);
+    if ask(&question)? {
+        let report = undo(Path::new(&config.target_dir))?;
+        say(config, false, undo_summary(config, &report));
+    }
+    Ok(())
+}
 
-    // Process in batches
-    for chunk in files_to_process.chunks(args.batch_size) {
-        process_batch(&client, &args, chunk).await?;
+/// Reverts the most recent run in the target directory's journal, and says how that went, or prints the report
+/// as JSON with --json
+fn undo_last_run(config: &SortConfig, quiet: bool) -> Result<()> {
+    let report = undo(Path::new(&config.target_dir))?;
+    if config.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        say(config, quiet, undo_summary(config, &report));
     }
+    Ok(())
+}
 
-    println!("Done!");
+fn undo_summary(config: &SortConfig, report: &UndoReport) -> String {
+    match report {
+        UndoReport { journal_found: false, .. } => format!("No journal found in {:?}. Nothing to undo.", config.target_dir),
+        UndoReport { files_found: 0, .. } => "Journal is empty. Nothing to undo.".to_string(),
+        UndoReport { files_found, reverted, .. } => format!("Reverted {} of {} files.", reverted, files_found),
+    }
+}
+
+/// Moves the files in the target directory's subfolders back up (unsort), listing the moves in a dry run, or prints
+/// the report as JSON with --json
+fn flatten(config: &SortConfig, quiet: bool) -> Result<()> {
+    let report = unsort(config)?;
+    if config.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if config.dry_run {
+        for file in &report.files {
+            say(config, quiet, format!("[DRY RUN] Would move '{}' -> '{}'", file.from.display(), file.to.display()));
+        }
+    }
+    let verb = if config.dry_run { "Would flatten" } else { "Flattened" };
+    say(config, quiet, format!("{} {} of {} files.", verb, report.files.len(), report.files_found));
     Ok(())
 }
 
-async fn process_batch(client: &Client, args: &Args, paths: &[PathBuf]) -> Result<()> {
-    let filenames: Vec<String> = paths.iter()
-        .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
-        .collect();
+/// Works out the moves as a dry run, lets the user review them in the terminal UI (--tui) and carries out
+/// the ones they approve. Files left out in the review count as skipped, as do all of them if the user quits
+/// or the run is interrupted before the review.
+async fn review(config: &SortConfig, interrupted: impl Future<Output = ()>, quiet: bool) -> Result<SortReport> {
+    let dry_config = SortConfig { dry_run: true, ..config.clone() };
+    let planned = sort_directory(&dry_config, !quiet, interrupted).await?;
+    let nothing_moved = SortReport {
+        files_found: planned.files_found,
+        skipped: planned.files_found,
+        interrupted: planned.interrupted,
+        ..SortReport::default()
+    };
+    if planned.interrupted || planned.files.is_empty() {
+        return Ok(nothing_moved);
+    }
 
-    let filenames_json = serde_json::to_string(&filenames).unwrap_or_else(|_| "[]".to_string());
+    let plan = Plan::from_report(&planned, Path::new(&config.target_dir), config.dest.as_deref(), &config.model);
+    let Some(plan) = tui::review(plan, config)? else {
+        say(config, quiet, "Quit the review; nothing was moved.".to_string());
+        return Ok(nothing_moved);
+    };
+    let left_out = planned.files.len() - plan.moves.len();
+    let report = plan.apply(config, !quiet).await?;
+    Ok(SortReport {
+        files_found: planned.files_found,
+        skipped: planned.skipped + left_out + report.skipped,
+        failed_batches: planned.failed_batches,
+        errors: planned.errors.into_iter().chain(report.errors).collect(),
+        ..report
+    })
+}
 
-    let prompt = format!(
-        "Analyze this list of filenames and assign a concise directory name for each.
-        Rules:
-        1. Group files primarily by file extension and type (e.g., all .mp3/.wav files should go to 'Music' or 'Audio', .jpg/.png to 'Images').
-        2. Do NOT translate Japanese or foreign filenames to English for the category name. Classify them by their file type (e.g. 'Music').
-        3. Use specific categories only if semantically distinct (e.g., 'Invoices' vs 'Documents').
-        Return ONLY a JSON object mapping filenames to directory names.
-        Filenames: {}
-        Example output: {{ \"song.mp3\": \"Music\", \"photo.jpg\": \"Images\", \"invoice.pdf\": \"Documents\" }}",
-        filenames_json
-    );
+/// Carries out the moves of the plan file named by --plan, which here is read rather than written
+async fn apply(config: &SortConfig, quiet: bool) -> Result<()> {
+    let Some(plan) = &config.plan else {
+        anyhow::bail!("apply needs the plan file to carry out, given with --plan");
+    };
+    let plan = Plan::load(plan)?;
+    let config = &SortConfig { plan: None, ..config.clone() };
+    config.validate()?;
+    if config.dry_run {
+        say(config, quiet, "*** DRY RUN: No files will be moved ***".to_string());
+    }
+    say(config, quiet, format!("Applying {} moves planned with model '{}' at {}...", plan.moves.len(), plan.model, plan.created));
+    let report = plan.apply(config, !quiet).await?;
+    finish(config, &report, quiet)?;
+    if report.interrupted {
+        offer_rollback(config, &report)?;
+        std::process::exit(130);
+    }
+    Ok(())
+}
 
-    let request = OllamaRequest {
-        model: args.model.clone(),
-        prompt: prompt.clone(),
-        stream: false,
-        format: "json".to_string(), // Tell Ollama to enforce JSON output
+/// Prints the models on the server as a table of names and sizes, or as JSON with --json
+async fn models(config: &SortConfig) -> Result<()> {
+    let mut models = list_models(config).await?;
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    if config.json {
+        println!("{}", serde_json::to_string_pretty(&models)?);
+    } else if models.is_empty() {
+        println!("The server has no models yet; pull one with `ollama pull <model>`.");
+    } else {
+        let width = models.iter().map(|m| m.name.chars().count()).chain(["Model".len()]).max().unwrap_or_default();
+        println!("{:<width$}  {:>10}", "Model", "Size");
+        for model in &models {
+            println!("{:<width$}  {:>10}", model.name, HumanBytes(model.size).to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Asks whether to pull the missing `model`. Without a terminal on stdin, the answer is no.
+fn confirm_pull(model: &str) -> Result<bool> {
+    ask(&format!("Model '{}' is not available on the server. Pull it now?", model))
+}
+
+/// Asks a yes/no `question` on stderr. Without a terminal on stdin, the answer is no.
+fn ask(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Pulls the configured model, showing the download of each layer as a progress bar
+async fn pull(config: &SortConfig, quiet: bool) -> Result<()> {
+    let bar = if quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{msg} {bar:40.cyan/blue} {bytes}/{total_bytes} ({eta} left)")
+                .expect("progress template is valid")
+                .progress_chars("##-"),
+        )
     };
+    let model = config.models()[0];
+    bar.set_message(format!("Pulling '{}'", model));
 
-    let max_retries = 3;
-    let mut mapping: Option<HashMap<String, String>> = None;
-
-    for attempt in 1..=max_retries {
-        let res = client.post(&args.api_url)
-            .json(&request)
-            .send()
-            .await;
-
-        match res {
-            Ok(response) => {
-                if !response.status().is_success() {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                    eprintln!("API Error (Attempt {}/{}): {} - {}", attempt, max_retries, status, error_text);
-                } else {
-                    match response.json::<OllamaResponse>().await {
-                        Ok(ollama_res) => {
-                            // Clean markdown if present
-                            let clean_json = ollama_res.response.trim();
-                            let clean_json = clean_json.strip_prefix("```json").unwrap_or(clean_json);
-                            let clean_json = clean_json.strip_prefix("```").unwrap_or(clean_json);
-                            let clean_json = clean_json.strip_suffix("```").unwrap_or(clean_json);
-                            
-                            match serde_json::from_str::<HashMap<String, String>>(clean_json) {
-                                Ok(map) => {
-                                    mapping = Some(map);
-                                    break;
-                                }
-                                Err(e) => {
-                                    eprintln!("JSON Parse Error (Attempt {}/{}): {}. Response was: {}", attempt, max_retries, e, ollama_res.response);
-                                }
-                            }
-                        }
-                        Err(e) => eprintln!("Failed to parse response body (Attempt {}/{}): {}", attempt, max_retries, e),
-                    }
-                }
+    let pulled = pull_model(config, &|progress| {
+        match (progress.completed, progress.total) {
+            (completed, Some(total)) => {
+                bar.set_length(total);
+                bar.set_position(completed.unwrap_or_default());
             }
-            Err(e) => eprintln!("Network Error (Attempt {}/{}): {}", attempt, max_retries, e),
+            _ if !quiet => bar.suspend(|| eprintln!("{}", progress.status)),
+            _ => {}
         }
+    })
+    .await;
+    bar.finish_and_clear();
+    pulled.with_context(|| format!("Could not pull model '{}'", model))
+}
 
-        if attempt < max_retries {
-            eprintln!("Retrying in 2 seconds...");
-            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-        }
-    }
+/// Sends log events to stderr: warnings and errors by default, fewer with --quiet and more with each --verbose.
+/// With --log-file, they're also appended to that file, at info level or above.
+fn init_logging(args: &Args) -> Result<()> {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    // Dependencies only get a say when something is wrong
+    let filter = |level| Targets::new().with_target("llm_sorter", level).with_default(LevelFilter::WARN.min(level));
 
-    let mapping = match mapping {
-        Some(m) => m,
-        None => {
-            eprintln!("Failed to process batch after {} attempts. Skipping batch.", max_retries);
-            return Ok(());
+    // The moves are printed in color on the terminal (see TERMINAL_TARGET) and only logged to the file
+    let stderr_filter = filter(level).with_target(MOVES_TARGET, LevelFilter::OFF);
+    let file_filter = |level| filter(level).with_target(TERMINAL_TARGET, LevelFilter::OFF);
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()))
+        .without_time()
+        .with_target(false)
+        .with_filter(stderr_filter);
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {:?}", path))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_target(false)
+                .with_filter(file_filter(level.max(LevelFilter::INFO)));
+            Some(layer)
         }
+        None => None,
     };
 
-    for path in paths {
-        let filename = path.file_name().unwrap().to_string_lossy().to_string();
-        if let Some(category) = mapping.get(&filename) {
-            let sanitized_category = category.chars().filter(|c| c.is_alphanumeric()).collect::<String>();
-            let sanitized_category = if sanitized_category.is_empty() { "Other".to_string() } else { sanitized_category };
-
-            let target_dir = Path::new(&args.target_dir).join(&sanitized_category);
-            
-            if args.dry_run {
-                println!("[DRY RUN] Would move '{}' -> '{}'", filename, sanitized_category);
-            } else {
-                if !target_dir.exists() {
-                    fs::create_dir_all(&target_dir).context("Failed to create category directory")?;
-                }
+    tracing_subscriber::registry().with(stderr).with(file).init();
+    Ok(())
+}
 
-                let new_path = target_dir.join(path.file_name().unwrap());
-                println!("Moving '{}' -> '{}'", filename, sanitized_category);
-                fs::rename(path, new_path).ok(); // Use ok() to avoid stopping the whole batch on one failure
-            }
+/// Prints the report of a finished run: as JSON with --json, otherwise as a summary table unless --quiet
+fn finish(config: &SortConfig, report: &SortReport, quiet: bool) -> Result<()> {
+    if config.json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+    if quiet {
+        return Ok(());
+    }
+    if report.files_found == 0 {
+        println!("No files found to sort.");
+        return Ok(());
+    }
+
+    print_report(report, &report_verb(config));
+
+    if !report.interrupted {
+        println!("Done!");
+    }
+    Ok(())
+}
+
+/// Prints the reports of runs over several directories: a summary table for each and a grand total,
+/// or with --json the combined report
+fn finish_all(configs: &[SortConfig], reports: &[SortReport], quiet: bool) -> Result<()> {
+    let mut total = SortReport::default();
+    for report in reports {
+        total.merge(report.clone());
+    }
+    let config = &configs[0];
+    if config.json {
+        println!("{}", serde_json::to_string_pretty(&total)?);
+        return Ok(());
+    }
+    if quiet {
+        return Ok(());
+    }
+
+    let verb = report_verb(config);
+    for (config, report) in configs.iter().zip(reports) {
+        println!();
+        println!("== {} ==", config.target_dir);
+        if report.files_found == 0 {
+            println!("No files found to sort.");
+        } else {
+            print_report(report, &verb);
         }
     }
 
+    println!();
+    println!("== Total ==");
+    println!(
+        "{} {} files in {} directories ({})",
+        verb,
+        total.moved,
+        reports.len(),
+        HumanBytes(total.bytes_moved)
+    );
+    println!("Skipped: {}, Failed: {}", total.skipped, total.failed);
+    if total.failed_batches > 0 {
+        println!("{} batches could not be categorized; see the errors above", total.failed_batches);
+    }
+    if !total.interrupted {
+        println!("Done!");
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// How the summary describes the files of a run: as moved (or copied), or as what would have been
+fn report_verb(config: &SortConfig) -> String {
+    if config.dry_run || config.plan.is_some() {
+        format!("Would {}", config.mode.infinitive())
+    } else {
+        config.mode.past().to_string()
+    }
+}
+
+/// Prints the per-category counts as a table, followed by the totals
+fn print_report(report: &SortReport, verb: &str) {
+    let width = report.categories.keys().map(|c| c.chars().count()).chain(["Category".len()]).max().unwrap_or_default();
+
+    println!();
+    if !report.categories.is_empty() {
+        println!("{:<width$}  {:>6}", "Category", "Files");
+        for (category, count) in &report.categories {
+            // Padded before it's colored, as the escape codes would count towards the width
+            let category = format!("{:<width$}", category);
+            println!("{}  {:>6}", category.if_supports_color(Stream::Stdout, |c| c.style(Style::new().bold().cyan())), count);
+        }
+        println!();
+    }
+    if !report.models.is_empty() {
+        println!("Categorized by model:");
+        for (model, count) in &report.models {
+            println!("  {}: {} files", model, count);
+        }
+        println!();
+    }
+    if !report.snapped.is_empty() {
+        println!("Put into existing folders (--fuzzy-match):");
+        for (category, folder) in &report.snapped {
+            println!("  '{}' -> '{}'", category, folder);
+        }
+        println!();
+    }
+    println!(
+        "{} {} files into {} categories ({})",
+        verb,
+        report.moved,
+        report.categories.len(),
+        HumanBytes(report.bytes_moved)
+    );
+    println!("Skipped: {}, Failed: {}", report.skipped, report.failed);
+    if report.failed_batches > 0 {
+        println!("{} batches could not be categorized; see the errors above", report.failed_batches);
+    }
+    if report.interrupted {
+        println!("Interrupted: the files of the remaining batches were left where they are");
+    }
+    if report.offline {
+        println!("Heuristic sort: the categories come from file extensions, not a model");
+    }
+    if !report.timings.is_empty() {
+        print_timings(&report.timings);
+    }
+}
+
+/// Prints the minimum, median, maximum and total of the request and parse times measured by --bench
+fn print_timings(timings: &[RequestTiming]) {
+    let files: usize = timings.iter().map(|t| t.files).sum();
+    println!();
+    println!("{} requests for {} files ({:.1} files per request)", timings.len(), files, files as f64 / timings.len() as f64);
+    println!("{:<8}  {:>10}  {:>10}  {:>10}  {:>10}", "", "Min", "Median", "Max", "Total");
+    for (name, mut times) in [
+        ("Request", timings.iter().map(|t| t.request_ms).collect::<Vec<_>>()),
+        ("Parse", timings.iter().map(|t| t.parse_ms).collect()),
+    ] {
+        times.sort_by(f64::total_cmp);
+        let median = match times.len() {
+            n if n % 2 == 0 => (times[n / 2 - 1] + times[n / 2]) / 2.0,
+            n => times[n / 2],
+        };
+        let total: f64 = times.iter().sum();
+        let ms = |t: f64| format!("{:.2} ms", t);
+        println!("{:<8}  {:>10}  {:>10}  {:>10}  {:>10}", name, ms(times[0]), ms(median), ms(times[times.len() - 1]), ms(total));
+    }
+}
+
+/// Parses the command line, using values from the config file as defaults.
+/// Precedence is: explicit CLI flags, then the config file, then the built-in defaults.
+fn parse_args() -> Result<Args> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let mut command = Args::command();
+
+    let explicit_config = config_path_from_argv(&argv);
+    if let Some(path) = explicit_config.clone().or_else(default_config_path) {
+        if path.exists() {
+            command = apply_config_defaults(command, &path)?;
+        } else if explicit_config.is_some() {
+            anyhow::bail!("Config file not found: {:?}", path);
+        }
+    }
+
+    let matches = command.try_get_matches_from_mut(argv).unwrap_or_else(|e| e.exit());
+    reject_misplaced_options(&mut command, &matches);
+    Ok(Args::from_arg_matches(&matches)?)
+}
+
+/// Exits with an error if options of `sort` were given ahead of a subcommand, where they'd be silently ignored.
+/// Only the global options can go anywhere.
+fn reject_misplaced_options(command: &mut clap::Command, matches: &clap::ArgMatches) {
+    let Some((name, _)) = matches.subcommand() else { return };
+    let misplaced = command.get_arguments()
+        .filter(|arg| !arg.is_global_set())
+        .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine));
+    if let Some(arg) = misplaced {
+        let flag = arg.get_long().map(|long| format!("--{}", long)).unwrap_or_else(|| arg.get_id().to_string());
+        command
+            .error(ErrorKind::ArgumentConflict, format!("'{}' must come after the '{}' subcommand", flag, name))
+            .exit();
+    }
+}
+
+/// Finds `--config <path>` ahead of the real parse, since it decides the defaults of every other flag
+fn config_path_from_argv(argv: &[OsString]) -> Option<PathBuf> {
+    let mut iter = argv.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return iter.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("sortify").join("config.toml"))
+}
+
+/// Overrides the defaults of `command`'s arguments with the matching keys of the TOML config file
+fn apply_config_defaults(mut command: clap::Command, path: &Path) -> Result<clap::Command> {
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read config file {:?}", path))?;
+    let table: toml::Table = contents.parse().with_context(|| format!("Malformed config file {:?}", path))?;
+
+    for (key, value) in table {
+        if key == "config" || !command.get_arguments().any(|arg| arg.get_id() == key.as_str()) {
+            anyhow::bail!("Unknown key '{}' in config file {:?}", key, path);
+        }
+
+        let values = match value {
+            toml::Value::Array(items) => items.iter().map(config_value_to_string).collect::<Result<Vec<_>>>(),
+            // A table like `[alias]` stands for repeated `key=value` flags
+            toml::Value::Table(entries) => entries.iter()
+                .map(|(k, v)| config_value_to_string(v).map(|v| format!("{}={}", k, v)))
+                .collect::<Result<Vec<_>>>(),
+            other => config_value_to_string(&other).map(|v| vec![v]),
+        }
+        .with_context(|| format!("Invalid value for '{}' in config file {:?}", key, path))?;
+
+        // Subcommands that flatten their own copy of an option need the default on that copy as well
+        let subcommands: Vec<String> = command.get_subcommands()
+            .filter(|sub| sub.get_arguments().any(|arg| arg.get_id() == key.as_str()))
+            .map(|sub| sub.get_name().to_string())
+            .collect();
+        for name in subcommands {
+            command = command.mut_subcommand(name, |sub| sub.mut_arg(&key, |arg| arg.default_values(values.clone())));
+        }
+        command = command.mut_arg(key, |arg| arg.default_values(values));
+    }
+
+    Ok(command)
+}
+
+fn config_value_to_string(value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => anyhow::bail!("unsupported value type '{}'", other.type_str()),
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::{Result, SortError};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Category layout for `--by-exif-date` when no `--date-format` is given
+pub const DEFAULT_EXIF_DATE_FORMAT: &str = "%Y/%m";
+
+/// Category layout for `--by-date` when no `--date-format` is given
+pub const DEFAULT_MTIME_DATE_FORMAT: &str = "%Y-%m";
+
+/// Finds files whose content is byte-identical to an earlier file in `files`, mapping each duplicate
+/// to the first-seen original. Only files sharing a size are hashed; unreadable files are never duplicates.
+pub fn find_duplicates(files: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let sized: Vec<(&PathBuf, u64)> = files.iter()
+        .filter_map(|path| fs::metadata(path).ok().map(|m| (path, m.len())))
+        .collect();
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for (_, size) in &sized {
+        *size_counts.entry(*size).or_default() += 1;
+    }
+
+    let mut originals: HashMap<blake3::Hash, &PathBuf> = HashMap::new();
+    let mut duplicates = HashMap::new();
+    for (path, size) in sized {
+        if size_counts[&size] < 2 { continue; }
+        let Ok(hash) = hash_file(path) else { continue };
+        match originals.get(&hash) {
+            Some(original) => { duplicates.insert(path.clone(), (*original).clone()); }
+            None => { originals.insert(hash, path); }
+        }
+    }
+    duplicates
+}
+
+/// The BLAKE3 hash of the contents of the file at `path`
+pub fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Reads the EXIF capture date of a JPEG/HEIC image and formats it as a `/`-separated category path.
+/// Returns `None` for other files and for images without a usable `DateTimeOriginal`.
+pub fn exif_date_category(path: &Path, date_format: &str) -> Option<String> {
+    let mime = sniff_type(path)?.mime_type();
+    if !matches!(mime, "image/jpeg" | "image/heif" | "image/heic") {
+        return None;
+    }
+
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut io::BufReader::new(file)).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    let exif::Value::Ascii(ref values) = field.value else { return None };
+    let taken = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    let date = chrono::NaiveDate::from_ymd_opt(taken.year.into(), taken.month.into(), taken.day.into())?
+        .and_hms_opt(taken.hour.into(), taken.minute.into(), taken.second.into())?;
+    Some(sanitize_date_category(&date.format(date_format).to_string()))
+}
+
+/// Keeps a formatted date usable as a nested category: each `/`-separated component is stripped of
+/// anything but alphanumerics, `-`, `_` and `.`, and empty or `.`/`..` components are dropped.
+pub fn sanitize_date_category(formatted: &str) -> String {
+    let components: Vec<String> = formatted.split('/')
+        .map(|component| component.chars().filter(|c| c.is_alphanumeric() || "-_.".contains(*c)).collect::<String>())
+        .filter(|component| !component.is_empty() && component != "." && component != "..")
+        .collect();
+    if components.is_empty() { "Other".to_string() } else { components.join("/") }
+}
+
+/// Names of the `--by-size` buckets, smallest first, indexed by the number of thresholds
+const SIZE_BUCKET_NAMES: [&[&str]; 5] = [
+    &["All"],
+    &["Small", "Large"],
+    &["Small", "Medium", "Large"],
+    &["Small", "Medium", "Large", "Huge"],
+    &["Tiny", "Small", "Medium", "Large", "Huge"],
+];
+
+/// Picks the bucket for a file of `size` bytes. A file belongs to the first bucket whose upper
+/// threshold it is below, so a size equal to a threshold lands in the next bucket up, and
+/// zero-byte files always land in the smallest one.
+pub fn size_category(size: u64, thresholds: &[u64]) -> &'static str {
+    let names = SIZE_BUCKET_NAMES[thresholds.len()];
+    let bucket = thresholds.iter().take_while(|&&threshold| size >= threshold).count();
+    names[bucket]
+}
+
+/// Names of all the `--by-size` buckets for `thresholds`
+pub fn size_bucket_names(thresholds: &[u64]) -> &'static [&'static str] {
+    SIZE_BUCKET_NAMES.get(thresholds.len()).copied().unwrap_or_default()
+}
+
+/// Whether `name` could be the first level of a date category in `date_format`, like `2024` for `%Y/%m`
+pub fn is_date_category(name: &str, date_format: &str) -> bool {
+    let first_level = date_format.split('/').next().unwrap_or_default();
+    let mut parsed = chrono::format::Parsed::new();
+    chrono::format::parse(&mut parsed, name, chrono::format::StrftimeItems::new(first_level)).is_ok()
+}
+
+pub fn validate_size_buckets(thresholds: &[u64]) -> Result<()> {
+    if thresholds.len() >= SIZE_BUCKET_NAMES.len() {
+        return Err(SortError::InvalidConfig(format!(
+            "At most {} size thresholds are supported",
+            SIZE_BUCKET_NAMES.len() - 1
+        )));
+    }
+    if thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(SortError::InvalidConfig("Size thresholds must be in ascending order".to_string()));
+    }
+    Ok(())
+}
+
+/// Fails if `format` contains an invalid strftime specifier, which chrono would otherwise only report by panicking
+pub fn validate_date_format(format: &str) -> Result<()> {
+    if chrono::format::StrftimeItems::new(format).any(|item| matches!(item, chrono::format::Item::Error)) {
+        return Err(SortError::InvalidConfig(format!("Invalid date format '{}'", format)));
+    }
+    Ok(())
+}
+
+/// How much of a file `infer` gets to see when sniffing its type
+const SNIFF_BYTES: usize = 8192;
+
+/// Reads up to `len` bytes from the start of a file
+fn read_header(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(len);
+    fs::File::open(path)?.take(len as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Detects a file's type from its first bytes, if it's a type `infer` knows
+fn sniff_type(path: &Path) -> Option<infer::Type> {
+    read_header(path, SNIFF_BYTES).ok().and_then(|header| infer::get(&header))
+}
+
+/// Labels a file for the prompt with the content type sniffed from its magic bytes, e.g. `IMG001.dat (image/png)`,
+/// or for text files with their first `peek_bytes` (`--peek-bytes`), e.g. `untitled.txt (text: "Dear landlord, ...")`.
+/// With `read_pdf` (`--read-pdf`), PDFs get a snippet of the text on their first page the same way.
+/// All of it is best-effort: unreadable or unrecognized files are described by their name alone.
+pub fn describe_file(path: &Path, name: &str, peek_bytes: usize, read_pdf: bool) -> String {
+    let Ok(header) = read_header(path, SNIFF_BYTES.max(peek_bytes)) else {
+        return name.to_string();
+    };
+    let kind = infer::get(&header);
+    let snippet = match kind {
+        Some(kind) if kind.mime_type() == "application/pdf" && read_pdf => pdf_snippet(path, peek_bytes),
+        Some(kind) if kind.matcher_type() != infer::MatcherType::Text => None,
+        _ => text_snippet(&header[..peek_bytes.min(header.len())]),
+    };
+
+    match (kind, snippet) {
+        (Some(kind), Some(snippet)) => format!("{} ({}: \"{}\")", name, kind.mime_type(), snippet),
+        (Some(kind), None) => format!("{} ({})", name, kind.mime_type()),
+        (None, Some(snippet)) => format!("{} (text: \"{}\")", name, snippet),
+        (None, None) => name.to_string(),
+    }
+}
+
+/// The start of the text on a PDF's first page, up to `len` bytes, or `None` if there's none or it can't be read.
+/// The extractor panics on some malformed PDFs, which counts as a failure like any other.
+fn pdf_snippet(path: &Path, len: usize) -> Option<String> {
+    let extract = || {
+        let doc = pdf_extract::Document::load(path).ok().filter(|doc| !doc.is_encrypted())?;
+        let mut text = String::new();
+        pdf_extract::output_doc_page(&doc, &mut pdf_extract::PlainTextOutput::new(&mut text), 1).ok()?;
+        Some(text)
+    };
+    let text = std::panic::catch_unwind(extract).ok().flatten()?;
+    text_snippet(&text.as_bytes()[..text.floor_char_boundary(len)])
+}
+
+/// Turns the first bytes of a file into a single line of text for the prompt, or `None` if they aren't text.
+/// A character cut off at the end is dropped, while invalid UTF-8 or NUL bytes anywhere else mean binary.
+/// Control characters and runs of whitespace become single spaces; quoting is left to the prompt's JSON encoding.
+fn text_snippet(bytes: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+
+    let snippet = text.split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!snippet.is_empty()).then_some(snippet)
+}
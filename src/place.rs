@@ -0,0 +1,227 @@
+use crate::config::{ConflictPolicy, SortConfig, TransferMode};
+use crate::error::{display_chain, Result, SortError};
+use crate::journal::Journal;
+use crate::report::SortReport;
+use crate::style;
+use crate::transfer::{copy_file, hard_link, make_room, move_file, resolve_conflict, symlink_to};
+use indicatif::ProgressBar;
+use owo_colors::Stream;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{error, info, warn, Level};
+
+/// Log target of the line about each move, for logs that keep a record of the moves. The binary sends it to the
+/// log file only.
+pub const MOVES_TARGET: &str = "llm_sorter::moves";
+
+/// Target that, where logging is enabled for it at info level, has the line about each move printed on stderr as
+/// well, in color. Log messages can't carry colors, so the binary shows the move lines this way rather than
+/// through [`MOVES_TARGET`].
+pub const TERMINAL_TARGET: &str = "llm_sorter::terminal";
+
+/// Carries out the moves of a run: conflict handling, `--interactive` confirmation, the dry run, the undo journal
+/// and the report. All output goes through `progress.suspend` so it doesn't tear the bar.
+pub struct Placer<'a> {
+    config: &'a SortConfig,
+    progress: &'a ProgressBar,
+    journal: Journal,
+    /// Still asking before each move (`--interactive`), until the user answers "all"
+    confirm_each: AtomicBool,
+    /// The least time between two moves, if throttled (`--throttle-ms`, `--max-moves-per-sec`)
+    interval: Option<Duration>,
+    /// The earliest the next move may be made, shared by all batches so the limit holds for the run as a whole
+    next_move: Mutex<Option<Instant>>,
+    /// Set once the run is interrupted, after which no further file is placed
+    stopped: watch::Sender<bool>,
+    /// The width of the filenames in the lines about each file, so their arrows line up (see [`style::name_width`])
+    name_width: usize,
+    /// Whether a dry run prints a line for each file it would move
+    preview: bool,
+}
+
+impl<'a> Placer<'a> {
+    pub fn new(config: &'a SortConfig, progress: &'a ProgressBar, name_width: usize, preview: bool) -> Self {
+        let interactive = config.interactive && !config.dry_run;
+        if interactive && !io::stdin().is_terminal() {
+            warn!("--interactive needs a terminal on stdin; no files will be {}.", config.mode.past().to_lowercase());
+        }
+        Placer {
+            config,
+            progress,
+            journal: Journal::new(Path::new(&config.target_dir), config.dest.as_deref()),
+            confirm_each: AtomicBool::new(interactive),
+            interval: config.move_interval(),
+            next_move: Mutex::new(None),
+            stopped: watch::Sender::new(false),
+            name_width,
+            preview: preview && !config.json,
+        }
+    }
+
+    /// Moves (or copies) `path` to `destination` in the directory of `category`, unless the conflict policy or
+    /// the user says otherwise, and records the outcome in `report`. A failed transfer, or category directory that
+    /// can't be created, is reported rather than returned, so one bad file doesn't stop the rest. The lines about
+    /// the file call it `filename`, which in recursive mode is its path below the target directory. Quitting at
+    /// the --interactive prompt [stops](Self::stop) the placer.
+    pub async fn place(
+        &self,
+        path: &Path,
+        filename: &str,
+        destination: PathBuf,
+        category: &str,
+        report: &mut SortReport,
+    ) -> Result<()> {
+        let Placer { config, progress, .. } = self;
+        let size = fs::symlink_metadata(path).map(|m| m.len()).unwrap_or_default();
+
+        let Some(new_path) = resolve_conflict(path, destination, config.on_conflict) else {
+            let existing = if config.on_conflict == ConflictPolicy::HashSkip { "an identical file" } else { "it" };
+            progress.suspend(|| info!("Skipping '{}': {} already exists in '{}'", filename, existing, category));
+            report.skipped += 1;
+            return Ok(());
+        };
+        let new_name = match new_path.file_name() {
+            Some(name) if Some(name) != path.file_name() => Some(name.to_string_lossy()),
+            _ => None,
+        };
+        let renamed = new_name.as_ref().map(|name| format!(" (as '{}')", name)).unwrap_or_default();
+        let name = style::padded_name(filename, self.name_width);
+
+        // The preview is what a dry run is for, so it's printed rather than logged
+        if config.dry_run {
+            if self.preview {
+                let out = Stream::Stdout;
+                let renamed = new_name.map(|name| format!(" (as {})", style::new_name(&name, out))).unwrap_or_default();
+                progress.suspend(|| println!(
+                    "{} Would {} {} {} {}{}",
+                    style::dim("[DRY RUN]", out), config.mode.infinitive(), name, style::dim("->", out), style::category(category, out), renamed
+                ));
+            }
+            report.record_moved(path, &new_path, category, size);
+            return Ok(());
+        }
+
+        if self.confirm_each.load(Ordering::Relaxed) {
+            let question = format!("{} '{}' -> '{}'{}?", config.mode.imperative(), filename, category, renamed);
+            match progress.suspend(|| confirm(&question)).map_err(SortError::io("Failed to read confirmation"))? {
+                Confirmation::Yes => {}
+                Confirmation::No => {
+                    report.skipped += 1;
+                    return Ok(());
+                }
+                Confirmation::All => self.confirm_each.store(false, Ordering::Relaxed),
+                Confirmation::Quit => {
+                    // Ends the run as Ctrl-C does, so the moves made so far are still reported
+                    self.stop();
+                    return Ok(());
+                }
+            }
+        }
+
+        if !self.throttle().await {
+            return Ok(());
+        }
+        progress.suspend(|| {
+            info!(target: MOVES_TARGET, "{} {} -> '{}'{}", config.mode.progressive(), name, category, renamed);
+            if tracing::enabled!(target: TERMINAL_TARGET, Level::INFO) {
+                let out = Stream::Stderr;
+                let renamed = new_name.map(|name| format!(" (as {})", style::new_name(&name, out))).unwrap_or_default();
+                eprintln!("{} {} {} {}{}", config.mode.progressive(), name, style::dim("->", out), style::category(category, out), renamed);
+            }
+        });
+        // create_dir_all tolerates the directory appearing concurrently from another batch
+        let create_dir = new_path.parent().map_or(Ok(()), fs::create_dir_all);
+        // --on-conflict overwrite has a link replace the file in its way, unless that's the file itself
+        let overwrite = config.on_conflict == ConflictPolicy::Overwrite && new_path != path;
+        let make_room = || if overwrite { make_room(&new_path) } else { Ok(()) };
+        let transferred = create_dir.and_then(|()| match config.mode {
+            TransferMode::Move => move_file(path, &new_path),
+            TransferMode::Copy => copy_file(path, &new_path, config.follow_symlinks, !config.no_preserve),
+            TransferMode::Symlink => make_room().and_then(|()| symlink_to(path, &new_path)),
+            TransferMode::Hardlink => make_room().and_then(|()| hard_link(path, &new_path)),
+        });
+        match transferred {
+            Ok(()) => {
+                if let Err(e) = self.journal.record(path, &new_path, config.mode) {
+                    progress.suspend(|| warn!("Could not journal {} of '{}': {}", config.mode.infinitive(), filename, display_chain(&e)));
+                }
+                report.record_moved(path, &new_path, category, size);
+            }
+            Err(source) => {
+                let error = SortError::MoveFailed { from: path.to_path_buf(), to: new_path, source };
+                progress.suspend(|| error!("{}", display_chain(&error)));
+                report.errors.push(display_chain(&error));
+                report.failed += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops placing files: those still to come are left where they are, and a wait for the throttle ends early
+    pub fn stop(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    /// Whether [`stop`](Self::stop) has been called
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.borrow()
+    }
+
+    /// Completes once [`stop`](Self::stop) has been called
+    pub async fn stopped(&self) {
+        // The sender lives as long as the receiver is used here, so waiting can't fail
+        let _ = self.stopped.subscribe().wait_for(|&stopped| stopped).await;
+    }
+
+    /// Waits until the throttle allows another move, returning `false` if the run was stopped meanwhile. The slot
+    /// is taken before waiting, so that batches waiting at the same time go in turn, and other batches' requests
+    /// carry on during the wait.
+    async fn throttle(&self) -> bool {
+        let Some(interval) = self.interval else { return true };
+        let wait = {
+            let mut next_move = self.next_move.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_move.map_or(now, |next| next.max(now));
+            *next_move = Some(slot + interval);
+            slot - now
+        };
+        tokio::select! {
+            () = self.stopped() => false,
+            () = tokio::time::sleep(wait) => true,
+        }
+    }
+}
+
+/// Answers to the `--interactive` confirmation prompt
+enum Confirmation {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Asks `question` on stdin. Anything but an explicit yes declines, and without a terminal nothing is read at all.
+fn confirm(question: &str) -> io::Result<Confirmation> {
+    if !io::stdin().is_terminal() {
+        return Ok(Confirmation::No);
+    }
+
+    print!("{} [y/N/a/q] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(Confirmation::Quit);
+    }
+
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Confirmation::Yes,
+        "a" | "all" => Confirmation::All,
+        "q" | "quit" => Confirmation::Quit,
+        _ => Confirmation::No,
+    })
+}
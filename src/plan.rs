@@ -0,0 +1,114 @@
+use crate::config::SortConfig;
+use crate::error::{Result, SortError};
+use crate::history::record_history;
+use crate::place::Placer;
+use crate::report::SortReport;
+use crate::style;
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// Moves worked out by a sort run without carrying them out, saved with `--plan` for review and editing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    /// The model that chose the categories
+    pub model: String,
+    /// When the plan was made, as an RFC 3339 timestamp
+    pub created: String,
+    /// The directory that was sorted, for reference; sources are relative to it
+    pub target_dir: PathBuf,
+    /// Where the category folders go (`--dest`), if not in the target directory; destinations are relative to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest_dir: Option<PathBuf>,
+    pub moves: Vec<PlannedMove>,
+}
+
+/// A single planned move. The source is relative to the target directory, and the destination to
+/// the destination directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedMove {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+}
+
+impl Plan {
+    /// Turns the moves of a dry run over `target_dir` into a plan, with categories in `dest` if given
+    pub fn from_report(report: &SortReport, target_dir: &Path, dest: Option<&Path>, model: &str) -> Self {
+        let relative = |path: &Path, dir: &Path| path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+        let absolute = |dir: &Path| std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let dest_dir = dest.unwrap_or(target_dir);
+        Plan {
+            model: model.to_string(),
+            created: chrono::Utc::now().to_rfc3339(),
+            target_dir: absolute(target_dir),
+            dest_dir: dest.map(absolute),
+            moves: report.files.iter()
+                .map(|file| PlannedMove { source: relative(&file.from, target_dir), destination: relative(&file.to, dest_dir) })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(SortError::io(format!("Failed to read plan {:?}", path)))?;
+        serde_json::from_str(&contents).map_err(|e| SortError::InvalidConfig(format!("Malformed plan {:?}: {}", path, e)))
+    }
+
+    /// Carries out the planned moves in `config.target_dir` without asking the model, subject to the conflict
+    /// policy and the other placement options of `config`. Categories go in `config.dest`, or else the plan's
+    /// destination directory, or else the target directory. Moves whose source is gone, or that would reach
+    /// outside those directories (the plan may have been edited by hand), are skipped with a warning. Quitting at
+    /// the --interactive prompt ends it there, with the report marked interrupted.
+    pub async fn apply(&self, config: &SortConfig, preview: bool) -> Result<SortReport> {
+        let config = &SortConfig { dest: config.dest.clone().or_else(|| self.dest_dir.clone()), ..config.clone() };
+        config.validate()?;
+        let target_dir = Path::new(&config.target_dir);
+        let progress = ProgressBar::hidden();
+        let placer = Placer::new(config, &progress, style::name_width(self.moves.iter().map(shown_source)), preview);
+        let mut report = SortReport { files_found: self.moves.len(), ..SortReport::default() };
+
+        for planned in &self.moves {
+            if placer.is_stopped() {
+                break;
+            }
+            if !is_contained(&planned.source) || !is_contained(&planned.destination) {
+                warn!("'{}' -> '{}' leaves the target directory, skipping", planned.source.display(), planned.destination.display());
+                report.skipped += 1;
+                continue;
+            }
+            let source = target_dir.join(&planned.source);
+            if fs::symlink_metadata(&source).is_err() {
+                warn!("'{}' has moved or disappeared since planning, skipping", planned.source.display());
+                report.skipped += 1;
+                continue;
+            }
+
+            let category = planned.destination.parent().unwrap_or(Path::new(""));
+            let category: Vec<_> = category.iter().map(|c| c.to_string_lossy()).collect();
+            let destination = config.destination().join(&planned.destination);
+            placer.place(&source, &shown_source(planned), destination, &category.join("/"), &mut report).await?;
+        }
+        report.interrupted = placer.is_stopped();
+        record_history(config, &report);
+        Ok(report)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self).map_err(io::Error::from);
+        contents
+            .and_then(|contents| fs::write(path, contents + "\n"))
+            .map_err(SortError::io(format!("Failed to write plan {:?}", path)))
+    }
+}
+
+/// Whether a relative path from the plan stays inside the directory it's joined to
+fn is_contained(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// How the lines about a planned move name its file: the source with `/` between its parts, as a run names it
+fn shown_source(planned: &PlannedMove) -> String {
+    planned.source.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
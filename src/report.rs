@@ -0,0 +1,112 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Outcome of a sort run, accumulated batch by batch
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SortReport {
+    /// Number of files that were eligible for sorting
+    pub files_found: usize,
+    /// Files moved (or copied, or that would have been in dry-run mode)
+    pub moved: usize,
+    /// Files left where they were: the model couldn't categorize them, their name was taken in the
+    /// category (`--on-conflict skip`), or they were declined in interactive mode
+    pub skipped: usize,
+    /// Files whose move or copy failed
+    pub failed: usize,
+    /// Batches the model couldn't categorize within `--max-retries`, whose files were skipped
+    pub failed_batches: usize,
+    /// Number of files moved into each category
+    pub categories: BTreeMap<String, usize>,
+    /// Total size of the moved files, as far as it could be read
+    pub bytes_moved: u64,
+    /// Every file that was moved, in the order they were moved
+    pub files: Vec<FileMove>,
+    /// How many files each model of a `--model` fallback list categorized
+    pub models: BTreeMap<String, usize>,
+    /// Categories from the model that `--fuzzy-match` put into an existing folder, mapped to that folder
+    pub snapped: BTreeMap<String, String>,
+    /// How long each request to the model took, when timed with `--bench`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timings: Vec<RequestTiming>,
+    /// Whether the run was interrupted before every batch was done
+    pub interrupted: bool,
+    /// Whether the categories came from the built-in extension table rather than a model (`--offline`)
+    pub offline: bool,
+    /// Problems that cost files their place, like failed batches or moves
+    pub errors: Vec<String>,
+}
+
+/// A single file placed into its category
+#[derive(Debug, Clone, Serialize)]
+pub struct FileMove {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub category: String,
+    /// How sure the model was of the category, where it said (`--explain`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+}
+
+/// Outcome of undoing the most recent run recorded in the journal
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UndoReport {
+    /// Whether the target directory has a journal at all
+    pub journal_found: bool,
+    /// Moves, copies and links of the most recent run in the journal
+    pub files_found: usize,
+    /// Of those, the ones that were reverted
+    pub reverted: usize,
+}
+
+/// Outcome of moving the files in the subdirectories back up into the target directory (`unsort`)
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UnsortReport {
+    /// Files found in the subdirectories
+    pub files_found: usize,
+    /// Every file moved back up (or that would have been in dry-run mode), in the order they were moved, with the
+    /// top-level folder it came out of as its category
+    pub files: Vec<FileMove>,
+}
+
+/// How long a single request to the model took (`--bench`)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RequestTiming {
+    /// Files the request asked about
+    pub files: usize,
+    /// Milliseconds from sending the request to having the whole answer
+    pub request_ms: f64,
+    /// Milliseconds spent parsing the answer
+    pub parse_ms: f64,
+}
+
+impl SortReport {
+    pub(crate) fn record_moved(&mut self, from: &Path, to: &Path, category: &str, bytes: u64) {
+        self.moved += 1;
+        self.bytes_moved += bytes;
+        *self.categories.entry(category.to_string()).or_default() += 1;
+        self.files.push(FileMove { from: from.to_path_buf(), to: to.to_path_buf(), category: category.to_string(), confidence: None });
+    }
+
+    /// Adds the counts of `other`, e.g. a single batch, to this report
+    pub fn merge(&mut self, other: SortReport) {
+        self.files_found += other.files_found;
+        self.interrupted |= other.interrupted;
+        self.offline |= other.offline;
+        self.moved += other.moved;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.failed_batches += other.failed_batches;
+        self.bytes_moved += other.bytes_moved;
+        for (category, count) in other.categories {
+            *self.categories.entry(category).or_default() += count;
+        }
+        self.files.extend(other.files);
+        self.snapped.extend(other.snapped);
+        for (model, count) in other.models {
+            *self.models.entry(model).or_default() += count;
+        }
+        self.timings.extend(other.timings);
+        self.errors.extend(other.errors);
+    }
+}
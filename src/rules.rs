@@ -0,0 +1,95 @@
+use crate::error::{Result, SortError};
+use crate::transfer::split_extension;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The extension table of `--offline`, by category
+const BUILTIN: &[(&str, &[&str])] = &[
+    ("Music", &["mp3", "flac", "wav", "aac", "m4a", "ogg", "oga", "opus", "wma", "aiff", "aif", "alac", "mid", "midi"]),
+    ("Videos", &["mp4", "mkv", "mov", "avi", "wmv", "webm", "m4v", "mpg", "mpeg", "flv", "3gp"]),
+    ("Images", &["jpg", "jpeg", "png", "gif", "bmp", "webp", "heic", "heif", "tif", "tiff", "svg", "ico", "raw", "cr2", "nef", "arw", "dng", "psd"]),
+    ("Documents", &["pdf", "doc", "docx", "odt", "rtf", "txt", "md", "tex", "epub", "mobi", "pages"]),
+    ("Spreadsheets", &["xls", "xlsx", "ods", "csv", "tsv", "numbers"]),
+    ("Presentations", &["ppt", "pptx", "odp", "key"]),
+    ("Archives", &["zip", "rar", "7z", "tar", "gz", "tgz", "bz2", "xz", "zst", "tar.gz", "tar.bz2", "tar.xz", "tar.zst", "iso", "dmg"]),
+    ("Code", &["rs", "py", "js", "ts", "jsx", "tsx", "java", "kt", "c", "h", "cpp", "hpp", "cs", "go", "rb", "php", "swift", "sh", "ps1", "html", "css", "json", "yaml", "yml", "toml", "xml", "sql", "ipynb"]),
+    ("Installers", &["exe", "msi", "deb", "rpm", "apk", "pkg", "appimage"]),
+    ("Fonts", &["ttf", "otf", "woff", "woff2"]),
+];
+
+/// Fixed categories for files by extension or glob (`--rules`), so the model is only asked about the rest
+#[derive(Debug, Default)]
+pub struct Rules {
+    /// Lowercased extensions without the leading dot, like `mp3` or `tar.gz`, mapped to their category
+    extensions: HashMap<String, String>,
+    /// Glob patterns and their category, longest pattern first
+    globs: Vec<(GlobMatcher, String)>,
+}
+
+impl Rules {
+    /// Reads the rules from `path`: a JSON object if the file name ends in `.json`, and otherwise a TOML table,
+    /// mapping an extension (`mp3` or `.mp3`) or a glob (`invoice*.pdf`) to a category
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = |e: String| SortError::InvalidConfig(format!("Invalid rules file {:?}: {}", path, e));
+        let contents = fs::read_to_string(path).map_err(SortError::io(format!("Failed to read rules file {:?}", path)))?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let entries: BTreeMap<String, String> = if is_json {
+            serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?
+        } else {
+            toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?
+        };
+
+        let mut rules = Rules::default();
+        let mut globs = Vec::new();
+        for (pattern, category) in entries {
+            let pattern = pattern.trim();
+            if pattern.is_empty() || category.trim().is_empty() {
+                return Err(invalid("rules need a pattern and a category".to_string()));
+            }
+            if pattern.contains(['*', '?', '[', '{']) {
+                let glob = GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| invalid(format!("invalid glob '{}': {}", pattern, e)))?;
+                globs.push((pattern.len(), glob.compile_matcher(), category));
+            } else {
+                rules.extensions.insert(pattern.trim_start_matches('.').to_lowercase(), category);
+            }
+        }
+        // The more a pattern spells out, the more specific it's likely to be
+        globs.sort_by_key(|(len, _, _)| std::cmp::Reverse(*len));
+        rules.globs = globs.into_iter().map(|(_, glob, category)| (glob, category)).collect();
+        Ok(rules)
+    }
+
+    /// The built-in table of common extensions that `--offline` sorts by
+    pub fn builtin() -> Self {
+        let extensions = BUILTIN.iter()
+            .flat_map(|(category, extensions)| extensions.iter().map(|ext| (ext.to_string(), category.to_string())))
+            .collect();
+        Rules { extensions, globs: Vec::new() }
+    }
+
+    /// The category the rules give the file `name`: that of the longest glob matching it, or else that of its
+    /// full extension (`tar.gz`), or else that of its last one (`gz`), ignoring case
+    pub fn category(&self, name: &str) -> Option<&str> {
+        if let Some((_, category)) = self.globs.iter().find(|(glob, _)| glob.is_match(name)) {
+            return Some(category);
+        }
+        let (_, extension) = split_extension(name);
+        let full = extension.trim_start_matches('.').to_lowercase();
+        let last = full.rsplit('.').next().unwrap_or_default();
+        let category = [full.as_str(), last].into_iter()
+            .filter(|ext| !ext.is_empty())
+            .find_map(|ext| self.extensions.get(ext));
+        category.map(String::as_str)
+    }
+
+    /// Every category these rules can give a file
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.extensions.values().chain(self.globs.iter().map(|(_, category)| category)).map(String::as_str)
+    }
+}
@@ -0,0 +1,939 @@
+use crate::cache::Cache;
+use crate::category::{categories_to_fold, existing_categories, sanitize_filename, CategoryPaths, SanitizePolicy};
+use crate::collect::{build_batches, group_by_extension};
+use crate::config::{BackendKind, FormatMode, SortConfig, TransferMode};
+use crate::error::{display_chain, Result, SortError};
+use crate::learn::LearnedExamples;
+use crate::llm::{parse_mapping, FileAnswer, LlmBackend, OllamaBackend, OpenAiBackend, PromptTemplate, Sampling};
+use crate::metadata::{
+    describe_file, exif_date_category, find_duplicates, sanitize_date_category, size_category,
+    DEFAULT_EXIF_DATE_FORMAT, DEFAULT_MTIME_DATE_FORMAT,
+};
+use crate::place::Placer;
+use crate::report::{RequestTiming, SortReport};
+use crate::rules::Rules;
+use crate::style;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Client;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn, Level};
+
+/// Category that `--dedup` moves duplicate files into
+pub const DUPLICATES_CATEGORY: &str = "Duplicates";
+
+/// Category that empty files go into, unless `--skip-empty` leaves them be
+pub const EMPTY_CATEGORY: &str = "Empty";
+
+/// Category that `--max-categories` folds the smallest categories into
+pub const MISC_CATEGORY: &str = "Misc";
+
+/// How many of the directories already in the destination are named in the prompt, to keep it short
+const MAX_PREFERRED_CATEGORIES: usize = 100;
+
+/// Categorizes and moves `files`, which all lie in `config.target_dir` or below it. When `interrupted` completes,
+/// no further batch is started and the ones waiting for the model are dropped, while a batch already moving its
+/// files stops before the next one; the report then says the run was interrupted. A dry run prints a line for each
+/// file it would move if `preview` is set.
+pub async fn sort_files(
+    config: &SortConfig,
+    models: &[(String, Box<dyn LlmBackend>)],
+    files: &[PathBuf],
+    preview: bool,
+    interrupted: impl Future<Output = ()>,
+) -> Result<SortReport> {
+    let target_path = Path::new(&config.target_dir);
+    let mut report = SortReport { files_found: files.len(), offline: config.offline, ..SortReport::default() };
+    if files.is_empty() {
+        return Ok(report);
+    }
+
+    // Process in batches, each reporting what happened to its files
+    // Debug output comes from deep inside the requests, where the bar can't be suspended
+    let progress = if config.no_progress || tracing::enabled!(Level::DEBUG) {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(files.len() as u64).with_style(
+            ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} files ({eta} left)")
+                .expect("progress template is valid")
+                .progress_chars("##-"),
+        )
+    };
+
+    if config.clear_cache {
+        Cache::clear(target_path)?;
+    }
+    let cache = (!config.no_cache).then(|| Cache::load(target_path, &config.model));
+    let duplicates = if config.dedup { find_duplicates(files) } else { HashMap::new() };
+    let rules = config.rules.as_deref().map(Rules::load).transpose()?;
+    let target_dir = std::path::absolute(target_path).unwrap_or_else(|_| target_path.to_path_buf());
+    let name_width = style::name_width(files.iter().map(|path| file_key(path, &target_dir, config.recursive)));
+    let ctx = RunContext {
+        config,
+        models,
+        progress: &progress,
+        placer: Placer::new(config, &progress, name_width, preview),
+        duplicates,
+        cache,
+        category_paths: CategoryPaths::new(config),
+        rules,
+        target_dir,
+    };
+    let grouped;
+    let files = if config.group_by_ext {
+        grouped = group_by_extension(files);
+        &grouped
+    } else {
+        files
+    };
+    // Files the rules place need no model, so they go first and the model's batches are filled with the rest
+    let ruled;
+    let files = match &ctx.rules {
+        Some(rules) => {
+            ruled = rules_first(rules, files);
+            &ruled
+        }
+        None => files,
+    };
+    let chunks = build_batches(files, config.batch_size, config.max_prompt_chars, config.group_by_ext, |path| {
+        describe_file(path, &ctx.file_key(path), config.peek_bytes, config.read_pdf)
+    });
+    // Once interrupted, the batches waiting for the model are dropped, while those placing their files stop at
+    // the next one, so the report still holds every file that was moved
+    let interrupted = async {
+        interrupted.await;
+        ctx.placer.stop();
+        std::future::pending::<Infallible>().await
+    };
+    let batches = async {
+        match config.max_categories {
+            None => {
+                stream::iter(chunks)
+                    .map(|chunk| async {
+                        let sorted = process_batch(&ctx, chunk).await;
+                        progress.inc(chunk.len() as u64);
+                        sorted
+                    })
+                    .buffer_unordered(config.concurrency as usize)
+                    .try_collect::<Vec<_>>()
+                    .await
+            }
+            // Which categories are too small is only known once every file has one, so nothing moves until then
+            Some(max_categories) => {
+                let mut categorized: Vec<_> = stream::iter(chunks)
+                    .map(|chunk| {
+                        let (ctx, progress) = (&ctx, &progress);
+                        async move {
+                            let categorized = until_stopped(ctx, categorize_batch(ctx, chunk)).await?;
+                            progress.inc(chunk.len() as u64);
+                            Ok::<_, SortError>(categorized.map(|(placement, report)| (chunk, placement, report)))
+                        }
+                    })
+                    .buffer_unordered(config.concurrency as usize)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                // Placing only some of the files would fold categories by an incomplete count
+                if ctx.placer.is_stopped() {
+                    categorized.clear();
+                }
+                let mut categorized: Vec<_> = categorized.into_iter().flatten().collect();
+                fold_small_categories(&ctx, categorized.iter_mut().map(|(_, placement, _)| &mut placement.categories), max_categories);
+                let mut reports = Vec::new();
+                for (chunk, placement, mut report) in categorized {
+                    place_files(&ctx, chunk, &placement, &mut report).await?;
+                    reports.push(report);
+                }
+                Ok(reports)
+            }
+        }
+    };
+    let batches = tokio::select! {
+        batches = batches => batches?,
+        never = interrupted => match never {},
+    };
+    progress.finish_and_clear();
+
+    for batch in batches {
+        report.merge(batch);
+    }
+    report.interrupted = ctx.placer.is_stopped();
+    if config.prune_empty && !config.dry_run && config.mode == TransferMode::Move {
+        prune_empty_dirs(target_path, &report);
+    }
+    Ok(report)
+}
+
+/// Builds the HTTP client for the LLM, so a hung server fails the attempt after `--timeout-secs` instead of
+/// stalling the run. Every request carries the `--header`s. Proxies come from `--proxy`, or else the `HTTP_PROXY`/`HTTPS_PROXY` variables unless
+/// `--no-proxy` is given. `--ca-cert` adds a trusted root, while `--insecure` turns certificate checks off.
+pub fn build_client(config: &SortConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .default_headers(config.header.iter().cloned().collect());
+    if let Some(url) = &config.proxy {
+        let proxy = reqwest::Proxy::all(url).map_err(|e| SortError::InvalidConfig(format!("Invalid proxy '{}': {}", url, e)))?;
+        builder = builder.proxy(proxy);
+    } else if config.no_proxy {
+        builder = builder.no_proxy();
+    }
+    if let Some(path) = &config.ca_cert {
+        let contents = fs::read(path).map_err(SortError::io(format!("Failed to read CA certificate {:?}", path)))?;
+        let certificate = reqwest::Certificate::from_pem(&contents)
+            .or_else(|_| reqwest::Certificate::from_der(&contents))
+            .map_err(|e| SortError::InvalidConfig(format!("Invalid CA certificate {:?}: {}", path, e)))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    if config.insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder.build().map_err(|e| SortError::InvalidConfig(format!("Failed to set up the HTTP client: {}", e)))
+}
+
+/// Builds the backend selected by `config` for its first model, loading its prompt template
+pub fn build_backend(client: Client, config: &SortConfig) -> Result<Box<dyn LlmBackend>> {
+    let (_, backend) = build_backends(client, config)?.swap_remove(0);
+    Ok(backend)
+}
+
+/// Builds the backend selected by `config` for each of its models, in the order they're tried,
+/// loading the prompt template
+pub fn build_backends(client: Client, config: &SortConfig) -> Result<Vec<(String, Box<dyn LlmBackend>)>> {
+    let prompt = match &config.prompt_file {
+        Some(path) => {
+            let template = fs::read_to_string(path).map_err(SortError::io(format!("Failed to read prompt file {:?}", path)))?;
+            PromptTemplate::new(template)
+                .map_err(|e| SortError::InvalidConfig(format!("Invalid prompt file {:?}: {}", path, e)))?
+        }
+        None if config.rename => PromptTemplate::renaming(),
+        None => PromptTemplate::default(),
+    };
+    // With a fixed list there's nothing to prefer among the folders that happen to exist
+    let prompt = if config.categories.is_empty() {
+        let mut existing = existing_categories(config.destination());
+        existing.truncate(MAX_PREFERRED_CATEGORIES);
+        prompt.preferring(&existing)
+    } else {
+        prompt.restricted_to(&config.categories)
+    };
+
+    let prompt = if config.learn {
+        let learned = LearnedExamples::load(Path::new(&config.target_dir), config.destination(), !config.dry_run).examples;
+        let learned: BTreeMap<_, _> = learned.into_iter()
+            .filter(|(category, _)| config.categories.is_empty() || config.categories.contains(category))
+            .take(MAX_PREFERRED_CATEGORIES)
+            .collect();
+        prompt.learning_from(&learned)
+    } else {
+        prompt
+    };
+    let prompt = if config.explains() { prompt.explaining() } else { prompt };
+    // Files are keyed by their path below the target directory in recursive mode (see RunContext::file_key)
+    let prompt = if config.recursive { prompt.with_paths() } else { prompt };
+
+    let sampling = Sampling { temperature: config.temperature, top_p: config.top_p, seed: config.seed };
+    let backend = |model: &str| -> Box<dyn LlmBackend> {
+        match config.backend {
+            BackendKind::Ollama => Box::new(OllamaBackend {
+                client: client.clone(),
+                api_url: config.api_url.clone().unwrap_or_else(|| "http://localhost:11434/api/generate".to_string()),
+                model: model.to_string(),
+                prompt: prompt.clone(),
+                stream: config.stream,
+                json_schema: config.format_mode == FormatMode::Schema,
+                rename: config.rename,
+                explain: config.explains(),
+                categories: config.categories.clone(),
+                api_key: config.api_key.clone(),
+                sampling,
+                keep_alive: config.keep_alive.clone(),
+            }),
+            BackendKind::OpenAi => Box::new(OpenAiBackend {
+                client: client.clone(),
+                api_url: config.api_url.clone().unwrap_or_else(|| "http://localhost:11434/v1/chat/completions".to_string()),
+                model: model.to_string(),
+                prompt: prompt.clone(),
+                api_key: config.api_key.clone(),
+                sampling,
+            }),
+        }
+    };
+    Ok(config.models().into_iter().map(|model| (model.to_string(), backend(model))).collect())
+}
+
+/// Everything a batch needs while sorting, shared by all concurrently running batches
+pub struct RunContext<'a> {
+    pub config: &'a SortConfig,
+    /// The backends for the models of `--model`, in the order they're tried
+    pub models: &'a [(String, Box<dyn LlmBackend>)],
+    pub progress: &'a ProgressBar,
+    pub placer: Placer<'a>,
+    /// Files whose content matches an earlier file (`--dedup`), mapped to that first-seen file
+    pub duplicates: HashMap<PathBuf, PathBuf>,
+    /// Earlier model answers, unless disabled with `--no-cache`
+    pub cache: Option<Cache>,
+    /// Turns the categories of the run into the paths they go to
+    pub category_paths: CategoryPaths<'a>,
+    /// Fixed categories by extension or glob (`--rules`)
+    pub rules: Option<Rules>,
+    /// The absolute target directory, which the keys of files in recursive mode are relative to
+    pub target_dir: PathBuf,
+}
+
+impl RunContext<'_> {
+    /// The key of the file at `path` in a batch and in the model's answer: its filename, or in recursive mode
+    /// its `/`-separated path below the target directory, as files in different subdirectories can share a name
+    pub fn file_key(&self, path: &Path) -> String {
+        file_key(path, &self.target_dir, self.config.recursive)
+    }
+
+    /// Turns a category from the model (or the fallback) into the category path to use, noting in `report`
+    /// when `--fuzzy-match` put it into an existing folder
+    fn category_path(&self, category: &str, report: &mut SortReport) -> String {
+        let resolved = self.category_paths.resolve(category);
+        if resolved.disallowed {
+            self.progress.suspend(|| info!("'{}' is not an allowed category, using fallback '{}'", category, self.config.fallback_category));
+        }
+        if let Some((top, folder)) = resolved.snapped {
+            self.progress.suspend(|| info!("Putting category '{}' into existing folder '{}'", top, folder));
+            report.snapped.insert(top, folder);
+        }
+        resolved.path
+    }
+}
+
+/// Categorizes a batch of files and moves them into place.
+/// Returns what happened to each of the batch's files.
+/// All output goes through `progress.suspend` so it doesn't tear the bar, and still prints when the bar is hidden.
+pub async fn process_batch(ctx: &RunContext<'_>, paths: &[PathBuf]) -> Result<SortReport> {
+    let Some((placement, mut report)) = until_stopped(ctx, categorize_batch(ctx, paths)).await? else {
+        return Ok(SortReport::default());
+    };
+    place_files(ctx, paths, &placement, &mut report).await?;
+    Ok(report)
+}
+
+/// Categorizes a batch with `categorize`, or gives `None` if the run is stopped before it's done
+async fn until_stopped<T>(ctx: &RunContext<'_>, categorize: impl Future<Output = Result<T>>) -> Result<Option<T>> {
+    if ctx.placer.is_stopped() {
+        return Ok(None);
+    }
+    tokio::select! {
+        categorized = categorize => categorized.map(Some),
+        () = ctx.placer.stopped() => Ok(None),
+    }
+}
+
+/// Where the files of a batch go, keyed as by [`RunContext::file_key`]
+#[derive(Debug, Default)]
+pub struct BatchPlacement {
+    /// Sanitized, `/`-separated category paths; files without one stay where they are
+    pub categories: HashMap<String, String>,
+    /// Sanitized new filenames the model suggested (`--rename`)
+    pub renames: HashMap<String, String>,
+    /// How sure the model was of each category, where it said (`--explain`)
+    pub confidences: HashMap<String, f64>,
+    /// Files already accounted for in the report, which stay where they are
+    pub left: HashSet<String>,
+}
+
+/// Works out the category of each file in a batch, by the rules in effect or by asking the model.
+/// Returns where the files go, along with a report holding any errors on the way. Only a fatal error
+/// (see [`SortError::is_fatal`]) is returned, as it ends the run.
+pub async fn categorize_batch(ctx: &RunContext<'_>, paths: &[PathBuf]) -> Result<(BatchPlacement, SortReport)> {
+    let RunContext { config, progress, .. } = ctx;
+    let mut report = SortReport::default();
+    let mut renames = HashMap::new();
+    let mut confidences = HashMap::new();
+
+    // Categories decided without the model, keyed by file
+    let mut categories: HashMap<String, String> = HashMap::new();
+    // Files that stay where they are without the model: those that can't be read, and empty ones with --skip-empty
+    let mut left = HashSet::new();
+    for path in paths {
+        let filename = ctx.file_key(path);
+        match readable_size(path) {
+            Err(source) => {
+                let error = SortError::Io { context: format!("Cannot read '{}'", path.display()), source };
+                progress.suspend(|| error!("{}", display_chain(&error)));
+                report.errors.push(display_chain(&error));
+                report.failed += 1;
+                left.insert(filename);
+            }
+            Ok(0) if config.skip_empty => {
+                progress.suspend(|| info!("Skipping '{}': it is empty", filename));
+                report.skipped += 1;
+                left.insert(filename);
+            }
+            Ok(0) => {
+                categories.insert(filename, EMPTY_CATEGORY.to_string());
+            }
+            Ok(_) => {}
+        }
+    }
+    let paths: Vec<&Path> = paths.iter()
+        .map(PathBuf::as_path)
+        .filter(|path| !left.contains(&ctx.file_key(path)) && !categories.contains_key(&ctx.file_key(path)))
+        .collect();
+
+    for &path in &paths {
+        if let Some(original) = ctx.duplicates.get(path) {
+            progress.suspend(|| info!("'{}' is a duplicate of '{}'", path.display(), original.display()));
+            categories.insert(ctx.file_key(path), DUPLICATES_CATEGORY.to_string());
+        }
+    }
+
+    if let Some(rules) = &ctx.rules {
+        for &path in &paths {
+            let filename = ctx.file_key(path);
+            if categories.contains_key(&filename) { continue; }
+            if let Some(category) = rules.category(&file_name_string(path)) {
+                categories.insert(filename, ctx.category_path(category, &mut report));
+            }
+        }
+    }
+
+    if config.by_exif_date {
+        let date_format = config.date_format.as_deref().unwrap_or(DEFAULT_EXIF_DATE_FORMAT);
+        for &path in &paths {
+            if categories.contains_key(&ctx.file_key(path)) { continue; }
+            if let Some(category) = exif_date_category(path, date_format) {
+                categories.insert(ctx.file_key(path), category);
+            }
+        }
+    }
+
+    if config.by_date {
+        let date_format = config.date_format.as_deref().unwrap_or(DEFAULT_MTIME_DATE_FORMAT);
+        for &path in &paths {
+            let filename = ctx.file_key(path);
+            if categories.contains_key(&filename) { continue; }
+            match fs::metadata(path).and_then(|m| m.modified()) {
+                Ok(modified) => {
+                    let modified: chrono::DateTime<chrono::Local> = modified.into();
+                    categories.insert(filename, sanitize_date_category(&modified.format(date_format).to_string()));
+                }
+                Err(e) => progress.suspend(|| warn!("Could not read modification time of '{}': {}", filename, e)),
+            }
+        }
+    }
+
+    if config.by_size {
+        for &path in &paths {
+            let filename = ctx.file_key(path);
+            if categories.contains_key(&filename) { continue; }
+            match fs::metadata(path) {
+                Ok(metadata) => { categories.insert(filename, size_category(metadata.len(), &config.size_buckets).to_string()); }
+                Err(e) => progress.suspend(|| warn!("Could not read size of '{}': {}", filename, e)),
+            }
+        }
+    }
+
+    // Date and size modes never fall back to the model
+    if config.offline && !config.by_date && !config.by_size {
+        let builtin = Rules::builtin();
+        for &path in &paths {
+            let filename = ctx.file_key(path);
+            if categories.contains_key(&filename) { continue; }
+            let category = builtin.category(&file_name_string(path)).unwrap_or(&config.fallback_category);
+            categories.insert(filename, ctx.category_path(category, &mut report));
+        }
+    } else if !config.by_date && !config.by_size {
+        // The cache only knows categories, so with --rename every file goes to the model for a new name
+        if let Some(cache) = ctx.cache.as_ref().filter(|_| !config.rename) {
+            for &path in &paths {
+                let filename = ctx.file_key(path);
+                if categories.contains_key(&filename) { continue; }
+                if let Some(category) = cache.get(&file_name_string(path)) {
+                    categories.insert(filename, ctx.category_path(&category, &mut report));
+                }
+            }
+        }
+
+        let remaining: Vec<&Path> = paths.iter()
+            .copied()
+            .filter(|path| !categories.contains_key(&ctx.file_key(path)))
+            .collect();
+        if !remaining.is_empty() {
+            let filenames: Vec<String> = remaining.iter().map(|path| ctx.file_key(path)).collect();
+            let descriptions: Vec<String> = remaining.iter().zip(&filenames)
+                .map(|(path, name)| describe_file(path, name, config.peek_bytes, config.read_pdf))
+                .collect();
+            let retry = RetryPolicy::from_config(config);
+            let BatchAnswers { answers: mapping, failed, timings } = query_models(ctx, &filenames, &descriptions, &retry, &mut report).await?;
+            if config.bench {
+                report.timings.extend(timings);
+            }
+
+            // Files of the parts that failed are skipped, rather than given the fallback
+            let mut skipped = HashSet::new();
+            for (part, e) in failed {
+                let attempts = retry.retries + 1;
+                progress.suspend(|| error!("Failed to categorize {} files after {} attempts. Skipping them.", part.len(), attempts));
+                report.errors.push(format!("Failed to categorize batch after {} attempts: {}", attempts, display_chain(&e)));
+                report.failed_batches += 1;
+                skipped.extend(part);
+            }
+
+            // Answers too unsure to use aren't worth remembering either
+            let confident = |answer: &FileAnswer| match (config.min_confidence, answer.confidence) {
+                (Some(min), Some(confidence)) => confidence >= min,
+                _ => true,
+            };
+            // The cache goes by filename alone, wherever the file is. A dry run leaves the target directory as it was.
+            if let Some(cache) = ctx.cache.as_ref().filter(|_| !config.dry_run) {
+                let asked: HashSet<&String> = filenames.iter().collect();
+                let answers = mapping.iter()
+                    .filter(|(filename, answer)| asked.contains(filename) && confident(answer))
+                    .map(|(filename, answer)| (bare_name(filename), &answer.category));
+                if let Err(e) = cache.store(answers) {
+                    progress.suspend(|| warn!("{}", display_chain(&e)));
+                }
+            }
+            for filename in filenames {
+                if !mapping.contains_key(&filename) && !skipped.contains(&filename) {
+                    progress.suspend(|| warn!("Model omitted '{}', using fallback '{}'", filename, config.fallback_category));
+                    categories.insert(filename, ctx.category_path(&config.fallback_category, &mut report));
+                }
+            }
+            for (filename, answer) in mapping {
+                if let Some(reason) = &answer.reason {
+                    let confidence = answer.confidence.map(|c| format!(" ({:.2})", c)).unwrap_or_default();
+                    progress.suspend(|| info!("'{}' -> '{}'{}: {}", filename, answer.category, confidence, reason));
+                }
+                if let Some(confidence) = answer.confidence {
+                    confidences.insert(filename.clone(), confidence);
+                }
+                if !confident(&answer) {
+                    progress.suspend(|| warn!(
+                        "Model is only {:.2} sure of '{}' for '{}', using fallback '{}'",
+                        answer.confidence.unwrap_or_default(), answer.category, filename, config.fallback_category
+                    ));
+                    categories.insert(filename, ctx.category_path(&config.fallback_category, &mut report));
+                    continue;
+                }
+                let original = bare_name(&filename);
+                let new_name = answer.filename.filter(|_| config.rename)
+                    .and_then(|suggested| sanitize_filename(&suggested, original, &SanitizePolicy::from_config(config)));
+                if let Some(new_name) = new_name.filter(|new_name| new_name != original) {
+                    renames.insert(filename.clone(), new_name);
+                }
+                categories.insert(filename, ctx.category_path(&answer.category, &mut report));
+            }
+        }
+    }
+
+    Ok((BatchPlacement { categories, renames, confidences, left }, report))
+}
+
+/// Folds the least populated categories of a run into `Misc` until at most `max_categories` remain
+/// (`--max-categories`). `Duplicates` and `Empty` are neither counted nor folded.
+fn fold_small_categories<'a>(
+    ctx: &RunContext<'_>,
+    batches: impl Iterator<Item = &'a mut HashMap<String, String>>,
+    max_categories: usize,
+) {
+    let mut batches: Vec<_> = batches.collect();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for category in batches.iter().flat_map(|categories| categories.values()) {
+        if category != DUPLICATES_CATEGORY && category != EMPTY_CATEGORY {
+            *counts.entry(category.clone()).or_default() += 1;
+        }
+    }
+
+    let misc = ctx.category_paths.canonical(MISC_CATEGORY);
+    let folded = categories_to_fold(&counts, max_categories, &misc);
+    if folded.is_empty() {
+        return;
+    }
+    ctx.progress.suspend(|| info!("Folding {} small categories into '{}'", folded.len(), misc));
+    for category in batches.iter_mut().flat_map(|categories| categories.values_mut()) {
+        if folded.contains(category) {
+            category.clone_from(&misc);
+        }
+    }
+}
+
+/// The size of the file at `path`, once it's known to open for reading
+fn readable_size(path: &Path) -> std::io::Result<u64> {
+    // Links are placed as links, so their target needn't be readable
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.file_type().is_symlink() {
+        fs::File::open(path)?;
+    }
+    Ok(metadata.len())
+}
+
+/// Reorders `files` so those that `rules` has a category for come first, keeping their order otherwise
+fn rules_first(rules: &Rules, files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut ordered = files.to_vec();
+    ordered.sort_by_cached_key(|path| rules.category(&file_name_string(path)).is_none());
+    ordered
+}
+
+/// The key of the file at `path`, as [`RunContext::file_key`] gives it, for the absolute `target_dir`
+fn file_key(path: &Path, target_dir: &Path, recursive: bool) -> String {
+    if !recursive {
+        return file_name_string(path);
+    }
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match path.strip_prefix(target_dir) {
+        Ok(relative) => relative.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/"),
+        Err(_) => file_name_string(&path),
+    }
+}
+
+/// The filename at the end of a key from [`RunContext::file_key`]
+fn bare_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+/// The name of the file at `path`, as sent to the model outside recursive mode. Collected files always have a name in UTF-8 (see
+/// [`collect_files`](crate::collect::collect_files)), so this is exact for them.
+pub fn file_name_string(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+/// How many times a batch waits out a rate limit (HTTP 429) before it counts as a failed attempt
+const MAX_RATE_LIMITED_WAITS: u32 = 10;
+
+/// How failed model requests are retried
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; zero means a single attempt
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after it
+    pub base: Duration,
+    /// Upper bound for the delay, however many retries came before
+    pub max: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &SortConfig) -> Self {
+        RetryPolicy {
+            retries: config.max_retries,
+            base: Duration::from_millis(config.retry_base_ms),
+            max: Duration::from_millis(config.retry_max_ms),
+        }
+    }
+
+    /// The delay before retry number `retry` (counting from 1) without jitter: `base * 2^(retry - 1)`, capped at `max`
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base.saturating_mul(factor).min(self.max)
+    }
+
+    /// The backoff with random jitter taking off up to half, so concurrent batches don't retry in lockstep
+    fn jittered(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        backoff.mul_f64(rand::random_range(0.5..=1.0))
+    }
+}
+
+/// What the model answered for a batch
+#[derive(Debug, Default)]
+pub struct BatchAnswers {
+    /// The answers keyed by filename, with the categories as the model gave them
+    pub answers: HashMap<String, FileAnswer>,
+    /// Parts of the batch the model failed to answer, with the error of the final attempt
+    pub failed: Vec<(Vec<String>, SortError)>,
+    /// How long each request took, including those that failed
+    pub timings: Vec<RequestTiming>,
+}
+
+/// Asks the models of `--model` in turn to categorize a batch, each about the files the ones before it failed
+/// to answer after their retries. A fatal error only ends the run when it comes from the last model. With more
+/// than one model, `report` counts the files each of them answered for.
+async fn query_models(
+    ctx: &RunContext<'_>,
+    filenames: &[String],
+    descriptions: &[String],
+    retry: &RetryPolicy,
+    report: &mut SortReport,
+) -> Result<BatchAnswers> {
+    let RunContext { config, progress, models, .. } = ctx;
+    let (mut filenames, mut descriptions) = (filenames.to_vec(), descriptions.to_vec());
+    let mut answers = HashMap::new();
+    let mut timings = Vec::new();
+
+    for (i, (model, backend)) in models.iter().enumerate() {
+        let next = models.get(i + 1).map(|(next, _)| next);
+        let mut batch = match query_llm(backend.as_ref(), progress, &filenames, &descriptions, config.strict, retry).await {
+            Ok(batch) => batch,
+            Err(e) => match next {
+                Some(next) if e.is_fatal() => {
+                    progress.suspend(|| warn!("{}; trying '{}' instead", display_chain(&e), next));
+                    continue;
+                }
+                _ => return Err(e),
+            },
+        };
+
+        if models.len() > 1 && !batch.answers.is_empty() {
+            progress.suspend(|| info!("'{}' categorized {} files", model, batch.answers.len()));
+            *report.models.entry(model.clone()).or_default() += batch.answers.len();
+        }
+        answers.extend(std::mem::take(&mut batch.answers));
+        timings.append(&mut batch.timings);
+        match next {
+            Some(next) if !batch.failed.is_empty() => {
+                let failed: HashSet<String> = batch.failed.into_iter().flat_map(|(part, _)| part).collect();
+                progress.suspend(|| warn!("'{}' failed to categorize {} files, trying '{}' instead", model, failed.len(), next));
+                (filenames, descriptions) = filenames.into_iter().zip(descriptions)
+                    .filter(|(filename, _)| failed.contains(filename))
+                    .unzip();
+            }
+            _ => return Ok(BatchAnswers { answers, failed: batch.failed, timings }),
+        }
+    }
+    // Only reached without any models to ask
+    Ok(BatchAnswers { answers, failed: Vec::new(), timings })
+}
+
+/// Asks the model to categorize a batch, retrying on failure. `descriptions` are what the prompt lists
+/// for each of `filenames`, in the same order. Answers are gathered across attempts: when the model leaves
+/// files out, only those are asked about again, until every file has an answer or the attempts run out.
+/// An answer that can't be parsed, often cut short because the batch was too big, isn't retried as is: the
+/// files still unanswered are split in half and each half asked about on its own, down to single files.
+/// A rate limit (HTTP 429) is waited out for as long as the server's `Retry-After` asks, without using up an attempt.
+/// Only a fatal error is returned; other failures are listed in the answers.
+pub async fn query_llm(
+    backend: &dyn LlmBackend,
+    progress: &ProgressBar,
+    filenames: &[String],
+    descriptions: &[String],
+    strict: bool,
+    retry: &RetryPolicy,
+) -> Result<BatchAnswers> {
+    let mut answers = HashMap::new();
+    let mut timings = Vec::new();
+    // The files still without an answer, and their descriptions
+    let (mut filenames, mut descriptions) = (filenames.to_vec(), descriptions.to_vec());
+
+    let attempts = retry.retries + 1;
+    let mut attempt = 1;
+    let mut rate_limited = 0;
+    loop {
+        // Maps a described entry like "IMG001.dat (image/png)" back to its bare filename
+        let bare_names: HashMap<&String, &String> = descriptions.iter().zip(&filenames).collect();
+        let started = Instant::now();
+        let answer = backend.ask(&descriptions).await;
+        let request_time = started.elapsed();
+        debug!(attempt, files = descriptions.len(), elapsed = ?request_time, ok = answer.is_ok(), "LLM request finished");
+        let answer = answer.and_then(|text| parse_mapping(&text));
+        timings.push(RequestTiming {
+            files: descriptions.len(),
+            request_ms: request_time.as_secs_f64() * 1000.0,
+            parse_ms: (started.elapsed() - request_time).as_secs_f64() * 1000.0,
+        });
+        let answer = answer.and_then(|map| check_answer(map, &bare_names, strict, progress));
+        match answer {
+            Ok(map) => {
+                answers.extend(map);
+                (filenames, descriptions) = filenames.into_iter().zip(descriptions)
+                    .filter(|(filename, _)| !answers.contains_key(filename))
+                    .unzip();
+                if filenames.is_empty() || attempt == attempts {
+                    return Ok(BatchAnswers { answers, failed: Vec::new(), timings });
+                }
+                // Nothing went wrong on the server's side, so there's no need to back off
+                progress.suspend(|| info!("Model left out {} files, asking about them again ({}/{})", filenames.len(), attempt + 1, attempts));
+                attempt += 1;
+                continue;
+            }
+            Err(e) if e.is_fatal() => return Err(e),
+            // Being told to slow down isn't a failure, as long as it doesn't go on forever
+            Err(SortError::RateLimited { retry_after, .. }) if rate_limited < MAX_RATE_LIMITED_WAITS => {
+                rate_limited += 1;
+                let delay = retry_after.unwrap_or_else(|| retry.jittered(rate_limited));
+                progress.suspend(|| warn!("Rate limited by the server, waiting {:.1} seconds before trying again", delay.as_secs_f64()));
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(e @ SortError::ResponseParse { .. }) if filenames.len() > 1 => {
+                let half = filenames.len() / 2;
+                progress.suspend(|| warn!("Could not parse the answer for {} files, asking about each half on its own: {}", filenames.len(), display_chain(&e)));
+                let first = Box::pin(query_llm(backend, progress, &filenames[..half], &descriptions[..half], strict, retry)).await?;
+                let second = Box::pin(query_llm(backend, progress, &filenames[half..], &descriptions[half..], strict, retry)).await?;
+                answers.extend(first.answers.into_iter().chain(second.answers));
+                timings.extend(first.timings.into_iter().chain(second.timings));
+                return Ok(BatchAnswers { answers, failed: first.failed.into_iter().chain(second.failed).collect(), timings });
+            }
+            Err(e) => {
+                progress.suspend(|| warn!("Attempt {}/{} failed: {}", attempt, attempts, display_chain(&e)));
+                // The last attempt's error stands for the files still unanswered
+                if attempt == attempts {
+                    return Ok(BatchAnswers { answers, failed: vec![(filenames, e)], timings });
+                }
+            }
+        }
+
+        let delay = retry.jittered(attempt);
+        progress.suspend(|| info!("Retrying in {:.1} seconds...", delay.as_secs_f64()));
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Normalizes the model's keys to the bare filenames that were asked about, dropping (or, when `strict`,
+/// rejecting) any key that doesn't correspond to a file in the batch.
+fn check_answer(
+    map: HashMap<String, FileAnswer>,
+    bare_names: &HashMap<&String, &String>,
+    strict: bool,
+    progress: &ProgressBar,
+) -> Result<HashMap<String, FileAnswer>> {
+    let asked: HashSet<&String> = bare_names.values().copied().collect();
+    let mut answer = HashMap::new();
+    let mut hallucinated = Vec::new();
+
+    for (key, file_answer) in map {
+        // The model sometimes echoes the type hint back as part of the key, or escapes the quotes and backslashes
+        // of a name once more than the JSON needed
+        let key = if bare_names.contains_key(&key) { key } else { unescape_key(key) };
+        let filename = bare_names.get(&key).map_or(key, |name| name.to_string());
+        if asked.contains(&filename) {
+            answer.insert(filename, file_answer);
+        } else {
+            hallucinated.push(filename);
+        }
+    }
+
+    if !hallucinated.is_empty() {
+        if strict {
+            return Err(SortError::InvalidResponse(format!(
+                "model returned filenames that weren't in the batch: {:?}",
+                hallucinated
+            )));
+        }
+        progress.suspend(|| warn!("Ignoring filenames the model made up: {:?}", hallucinated));
+    }
+    Ok(answer)
+}
+
+/// `key` with one level of JSON string escaping undone (`a\"b` becomes `a"b`), or as it was if that isn't valid
+fn unescape_key(key: String) -> String {
+    if !key.contains('\\') {
+        return key;
+    }
+    serde_json::from_str(&format!("\"{}\"", key)).unwrap_or(key)
+}
+
+/// Moves (or copies) each file into its category directory under the destination, under its new name if
+/// it has one. Files without a category are left alone.
+async fn place_files(
+    ctx: &RunContext<'_>,
+    paths: &[PathBuf],
+    placement: &BatchPlacement,
+    report: &mut SortReport,
+) -> Result<()> {
+    for path in paths {
+        if ctx.placer.is_stopped() {
+            break;
+        }
+        let filename = ctx.file_key(path);
+        if placement.left.contains(&filename) {
+            continue;
+        }
+        let Some(category) = placement.categories.get(&filename) else {
+            report.skipped += 1;
+            continue;
+        };
+        let category_dir = ctx.config.destination().join(category.split('/').collect::<PathBuf>());
+        let Some(original_name) = path.file_name() else {
+            report.skipped += 1;
+            continue;
+        };
+        let name = placement.renames.get(&filename).map_or(original_name, |name| name.as_ref());
+        let placed = report.files.len();
+        ctx.placer.place(path, &filename, category_dir.join(name), category, report).await?;
+        if let Some(file) = report.files.get_mut(placed) {
+            file.confidence = placement.confidences.get(&filename).copied();
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the directories below `target_dir` that the moves in `report` left empty (`--prune-empty`), deepest
+/// first so that emptied parents go as well. The target directory itself and the category directories that
+/// received files are never removed.
+fn prune_empty_dirs(target_dir: &Path, report: &SortReport) {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let target_dir = absolute(target_dir);
+    let destinations: Vec<PathBuf> = report.files.iter().map(|file| absolute(&file.to)).collect();
+
+    let mut candidates: Vec<PathBuf> = report.files.iter()
+        .filter_map(|file| absolute(&file.from).parent().map(Path::to_path_buf))
+        .flat_map(|dir| dir.ancestors().map(Path::to_path_buf).collect::<Vec<_>>())
+        .filter(|dir| dir.starts_with(&target_dir) && *dir != target_dir)
+        .filter(|dir| !destinations.iter().any(|to| to.starts_with(dir)))
+        .collect();
+    // Deepest first, so each directory is empty by the time it's tried; the path keeps duplicates together
+    candidates.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then_with(|| a.cmp(b)));
+    candidates.dedup();
+
+    for dir in candidates {
+        // Only succeeds if the directory is empty
+        if fs::remove_dir(&dir).is_ok() {
+            info!("Removed empty directory '{}'", dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(category: &str) -> FileAnswer {
+        FileAnswer { category: category.to_string(), ..FileAnswer::default() }
+    }
+
+    #[test]
+    fn check_answer_maps_over_escaped_keys_back() {
+        let asked: Vec<String> = ["a\"b.mp3", "back\\slash.pdf", "{filenames}.txt", "c{d}e.jpg"].map(String::from).to_vec();
+        let bare_names: HashMap<&String, &String> = asked.iter().zip(&asked).collect();
+        // As if the model had written "a\\\"b.mp3" and "back\\\\slash.pdf" in its JSON
+        let map = HashMap::from([
+            ("a\\\"b.mp3".to_string(), answer("Music")),
+            ("back\\\\slash.pdf".to_string(), answer("Documents")),
+            ("{filenames}.txt".to_string(), answer("Text")),
+            ("c{d}e.jpg".to_string(), answer("Images")),
+        ]);
+        let mapped = check_answer(map, &bare_names, true, &ProgressBar::hidden()).unwrap();
+        assert_eq!(mapped["a\"b.mp3"].category, "Music");
+        assert_eq!(mapped["back\\slash.pdf"].category, "Documents");
+        assert_eq!(mapped["{filenames}.txt"].category, "Text");
+        assert_eq!(mapped["c{d}e.jpg"].category, "Images");
+    }
+
+    #[test]
+    fn check_answer_keeps_exact_keys_with_backslashes() {
+        let asked = vec!["back\\slash.pdf".to_string()];
+        let bare_names: HashMap<&String, &String> = asked.iter().zip(&asked).collect();
+        let map = HashMap::from([("back\\slash.pdf".to_string(), answer("Documents"))]);
+        let mapped = check_answer(map, &bare_names, true, &ProgressBar::hidden()).unwrap();
+        assert_eq!(mapped["back\\slash.pdf"].category, "Documents");
+    }
+
+    #[test]
+    fn check_answer_maps_described_keys_to_bare_names() {
+        let (described, bare) = ("IMG001.dat (image/png)".to_string(), "IMG001.dat".to_string());
+        let bare_names = HashMap::from([(&described, &bare)]);
+        let map = HashMap::from([(described.clone(), answer("Images"))]);
+        let mapped = check_answer(map, &bare_names, true, &ProgressBar::hidden()).unwrap();
+        assert_eq!(mapped["IMG001.dat"].category, "Images");
+    }
+
+    #[test]
+    fn check_answer_rejects_unknown_keys_when_strict() {
+        let asked = vec!["a.txt".to_string()];
+        let bare_names: HashMap<&String, &String> = asked.iter().zip(&asked).collect();
+        let map = HashMap::from([("made up.txt".to_string(), answer("Text"))]);
+        assert!(check_answer(map, &bare_names, true, &ProgressBar::hidden()).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use owo_colors::{OwoColorize, Stream, Style};
+
+/// Names longer than this don't widen the name column, so one long name doesn't push every arrow far right
+const MAX_NAME_WIDTH: usize = 40;
+
+/// The width of the name column in the lines about the files called `names`: the longest of them, up to
+/// [`MAX_NAME_WIDTH`]
+pub fn name_width<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> usize {
+    names.into_iter()
+        .map(|name| name.as_ref().chars().count())
+        .max()
+        .unwrap_or_default()
+        .min(MAX_NAME_WIDTH)
+}
+
+/// `name` in quotes, padded to a column of `width` names so the arrows after it line up
+pub fn padded_name(name: &str, width: usize) -> String {
+    // The quotes take up two more
+    format!("{:<width$}", format!("'{}'", name), width = width + 2)
+}
+
+/// `category` in quotes, highlighted when `stream` is a terminal that has colors and `NO_COLOR` isn't set
+pub fn category(category: &str, stream: Stream) -> String {
+    paint(&format!("'{}'", category), Style::new().bold().cyan(), stream)
+}
+
+/// A new filename (`--rename`, or a conflict) in quotes, highlighted like [`category`]
+pub fn new_name(name: &str, stream: Stream) -> String {
+    paint(&format!("'{}'", name), Style::new().yellow(), stream)
+}
+
+/// `text` that only frames the rest of a line, like the arrow or the `[DRY RUN]` tag, toned down on the same terms
+/// as [`category`]
+pub fn dim(text: &str, stream: Stream) -> String {
+    paint(text, Style::new().dimmed(), stream)
+}
+
+fn paint(text: &str, style: Style, stream: Stream) -> String {
+    text.if_supports_color(stream, |text| text.style(style)).to_string()
+}
@@ -0,0 +1,175 @@
+use crate::config::ConflictPolicy;
+use crate::metadata::hash_file;
+use filetime::FileTime;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Moves `from` to `to`. Renaming only works within one filesystem, so a move onto another mount
+/// falls back to copying and then deleting the source, once the copy is verified to be complete.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_file(from, to, false, true)?;
+            let (copied, original) = (fs::symlink_metadata(to)?, fs::symlink_metadata(from)?);
+            if copied.is_file() && copied.len() != original.len() {
+                // Leave the source alone; the partial copy is the only thing to clean up
+                let _ = fs::remove_file(to);
+                return Err(io::Error::other(format!(
+                    "copy is incomplete ({} of {} bytes)",
+                    copied.len(),
+                    original.len()
+                )));
+            }
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Copies `from` to `to`. A symlink is recreated as a link to the same target unless `follow_symlinks` is set,
+/// in which case the file it points to is copied. With `preserve`, a copied file gets the original's access and
+/// modification times and permissions.
+pub fn copy_file(from: &Path, to: &Path, follow_symlinks: bool, preserve: bool) -> io::Result<()> {
+    if !follow_symlinks && fs::symlink_metadata(from)?.file_type().is_symlink() {
+        let target = fs::read_link(from)?;
+        // Relative links would dangle once placed in another directory
+        let target = match from.parent() {
+            Some(parent) if target.is_relative() => parent.join(target),
+            _ => target,
+        };
+        return symlink_file(&target, to);
+    }
+    fs::copy(from, to)?;
+    if preserve {
+        copy_metadata(from, to)?;
+    }
+    Ok(())
+}
+
+/// Gives the file at `to` the times and permissions of the one at `from`. `fs::copy` already carries over the
+/// permissions (on Windows, the read-only attribute, and the times as well), but on Unix not the times.
+fn copy_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )?;
+    fs::set_permissions(to, metadata.permissions())
+}
+
+/// Creates a symbolic link at `link` to the file at `target`, by its absolute path so that the link works
+/// wherever it is
+pub fn symlink_to(target: &Path, link: &Path) -> io::Result<()> {
+    symlink_file(&std::path::absolute(target)?, link)
+}
+
+/// Creates a hard link at `link` to the file at `target`. Hard links can't span filesystems, which gets an
+/// error saying so rather than the system's.
+pub fn hard_link(target: &Path, link: &Path) -> io::Result<()> {
+    fs::hard_link(target, link).map_err(|e| match e.kind() {
+        io::ErrorKind::CrossesDevices => io::Error::new(
+            e.kind(),
+            "hard links only work within one filesystem; use --mode symlink or --mode copy instead",
+        ),
+        _ => e,
+    })
+}
+
+/// Removes the file at `path`, if there is one, so a link can take its place. Renaming and copying replace a file
+/// by themselves, but creating a link fails when something is already there.
+pub fn make_room(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Decides where `source`, bound for `dest`, should actually go, or `None` if it should be skipped.
+pub fn resolve_conflict(source: &Path, dest: PathBuf, policy: ConflictPolicy) -> Option<PathBuf> {
+    if !dest.exists() {
+        return Some(dest);
+    }
+
+    match policy {
+        ConflictPolicy::Skip => None,
+        ConflictPolicy::Overwrite => Some(dest),
+        ConflictPolicy::HashSkip if same_contents(source, &dest) => None,
+        ConflictPolicy::HashSkip => resolve_conflict(source, dest, ConflictPolicy::Rename),
+        ConflictPolicy::Rename => {
+            let name = dest.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let (stem, extension) = split_extension(&name);
+            (1..)
+                .map(|n| dest.with_file_name(format!("{} ({}){}", stem, n, extension)))
+                .find(|candidate| !candidate.exists())
+        }
+    }
+}
+
+/// Extensions that are followed by another one, as in `.tar.gz`, and belong to it
+const INNER_EXTENSIONS: &[&str] = &["tar"];
+
+/// Splits a file name into its stem and its full extension, dot included, so that `archive.tar.gz` gives
+/// `archive` and `.tar.gz`. A leading dot doesn't start an extension: `.bashrc` has none, `.config.json` has `.json`.
+pub fn split_extension(name: &str) -> (&str, &str) {
+    let last = match name.rfind('.') {
+        Some(i) if i > 0 && i + 1 < name.len() => i,
+        _ => return (name, ""),
+    };
+    let inner = name[..last].rfind('.').filter(|&i| {
+        i > 0 && INNER_EXTENSIONS.iter().any(|ext| name[i + 1..last].eq_ignore_ascii_case(ext))
+    });
+    name.split_at(inner.unwrap_or(last))
+}
+
+/// Whether the files at `a` and `b` hold the same bytes; `false` if either can't be read
+fn same_contents(a: &Path, b: &Path) -> bool {
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+    if size(a).is_none() || size(a) != size(b) {
+        return false;
+    }
+    matches!((hash_file(a), hash_file(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_extension_handles_missing_and_leading_dots() {
+        assert_eq!(split_extension("README"), ("README", ""));
+        assert_eq!(split_extension(".bashrc"), (".bashrc", ""));
+        assert_eq!(split_extension(".config.json"), (".config", ".json"));
+        assert_eq!(split_extension("name."), ("name.", ""));
+        assert_eq!(split_extension("a.b.c"), ("a.b", ".c"));
+    }
+
+    #[test]
+    fn split_extension_keeps_compound_extensions_together() {
+        assert_eq!(split_extension("archive.tar.gz"), ("archive", ".tar.gz"));
+        assert_eq!(split_extension("ARCHIVE.TAR.GZ"), ("ARCHIVE", ".TAR.GZ"));
+        assert_eq!(split_extension(".tar.gz"), (".tar", ".gz"));
+    }
+
+    #[test]
+    fn rename_numbers_before_a_compound_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.tar.gz");
+        let dest = dir.path().join("archive.tar.gz");
+        fs::write(&source, "new").unwrap();
+        fs::write(&dest, "old").unwrap();
+        let renamed = resolve_conflict(&source, dest, ConflictPolicy::Rename);
+        assert_eq!(renamed, Some(dir.path().join("archive (1).tar.gz")));
+    }
+}
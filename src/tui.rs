@@ -0,0 +1,207 @@
+use llm_sorter::{CategoryPaths, Plan, PlannedMove, SortConfig};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HELP: &str = "j/k move  g/G first/last  space include/leave out  c change category  a apply  q quit";
+
+/// A planned move as shown for review
+struct Entry {
+    /// Relative to the target directory, as in the plan
+    source: PathBuf,
+    /// The name the file gets in its category
+    name: OsString,
+    /// Sanitized, `/`-separated category path
+    category: String,
+    included: bool,
+}
+
+/// A line of the list: a category heading, or a file (by its index into the entries)
+enum Row {
+    Category(String, usize),
+    File(usize),
+}
+
+struct Review<'a> {
+    config: &'a SortConfig,
+    /// Turns the categories typed in into paths the way the run turned the model's
+    category_paths: CategoryPaths<'a>,
+    /// Sorted by category, then source
+    entries: Vec<Entry>,
+    /// Index into `entries` of the file under the cursor
+    selected: usize,
+    /// The category being typed for the selected file, while changing it
+    editing: Option<String>,
+    /// Feedback on the last action, shown in place of the help line
+    message: Option<String>,
+}
+
+/// Shows the moves of `plan` for review in the terminal, grouped by category. Returns the plan reduced to the
+/// moves the user kept, with the categories they chose, or `None` if they quit without applying.
+pub fn review(plan: Plan, config: &SortConfig) -> io::Result<Option<Plan>> {
+    let entries = plan.moves.iter()
+        .map(|planned| Entry {
+            source: planned.source.clone(),
+            name: planned.destination.file_name().unwrap_or_default().to_os_string(),
+            category: category_of(&planned.destination),
+            included: true,
+        })
+        .collect::<Vec<_>>();
+    let category_paths = CategoryPaths::new(config);
+    // A category typed in differently cased joins one of the plan's as it would have during the run
+    for entry in &entries {
+        category_paths.canonical(&entry.category);
+    }
+    let mut review = Review { config, category_paths, entries, selected: 0, editing: None, message: None };
+    review.sort();
+
+    let mut terminal = ratatui::init();
+    let applied = review.run(&mut terminal);
+    ratatui::restore();
+
+    Ok(applied?.then(|| Plan {
+        moves: review.entries.iter()
+            .filter(|entry| entry.included)
+            .map(|entry| PlannedMove {
+                source: entry.source.clone(),
+                destination: entry.category.split('/').collect::<PathBuf>().join(&entry.name),
+            })
+            .collect(),
+        ..plan
+    }))
+}
+
+/// The `/`-separated category of a planned destination
+fn category_of(destination: &Path) -> String {
+    let category = destination.parent().unwrap_or(Path::new(""));
+    category.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+impl Review<'_> {
+    /// Handles keys until the user applies (`true`) or quits (`false`)
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            // Raw mode turns Ctrl-C into a key like any other, so it quits here, also while editing
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(false);
+            }
+
+            if let Some(category) = &mut self.editing {
+                match key.code {
+                    KeyCode::Enter => self.finish_edit(),
+                    KeyCode::Esc => self.editing = None,
+                    KeyCode::Backspace => {
+                        category.pop();
+                    }
+                    KeyCode::Char(c) => category.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+
+            self.message = None;
+            let last = self.entries.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Char('j') | KeyCode::Down => self.selected = (self.selected + 1).min(last),
+                KeyCode::Char('k') | KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Char('g') | KeyCode::Home => self.selected = 0,
+                KeyCode::Char('G') | KeyCode::End => self.selected = last,
+                KeyCode::Char(' ') => {
+                    if let Some(entry) = self.entries.get_mut(self.selected) {
+                        entry.included = !entry.included;
+                    }
+                }
+                KeyCode::Char('c') => {
+                    self.editing = self.entries.get(self.selected).map(|entry| entry.category.clone());
+                }
+                KeyCode::Char('a') => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    /// Gives the selected file the category typed, turned into a path as the model's categories are. A blank
+    /// category, or one `--categories` doesn't allow, leaves the file where it was.
+    fn finish_edit(&mut self) {
+        let Some(typed) = self.editing.take() else { return };
+        let typed = typed.trim();
+        if typed.is_empty() {
+            self.message = Some("A category can't be blank".to_string());
+            return;
+        }
+        let resolved = self.category_paths.resolve(typed);
+        if resolved.disallowed {
+            self.message = Some(format!("'{}' isn't one of the allowed --categories", typed));
+            return;
+        }
+        if let Some((_, folder)) = &resolved.snapped {
+            self.message = Some(format!("Put into the existing folder '{}'", folder));
+        }
+        let Some(entry) = self.entries.get_mut(self.selected) else { return };
+        entry.category = resolved.path;
+        // The file moves to its new group, and the cursor with it
+        let source = entry.source.clone();
+        self.sort();
+        self.selected = self.entries.iter().position(|entry| entry.source == source).unwrap_or_default();
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by(|a, b| (&a.category, &a.source).cmp(&(&b.category, &b.source)));
+    }
+
+    /// The lines of the list, each category followed by its files
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i == 0 || self.entries[i - 1].category != entry.category {
+                let count = self.entries[i..].iter().take_while(|other| other.category == entry.category).count();
+                rows.push(Row::Category(entry.category.clone(), count));
+            }
+            rows.push(Row::File(i));
+        }
+        rows
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [list_area, status_area] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let rows = self.rows();
+        let selected_row = rows.iter().position(|row| matches!(row, Row::File(i) if *i == self.selected));
+        let items: Vec<ListItem> = rows.iter()
+            .map(|row| match row {
+                Row::Category(category, count) => ListItem::new(Line::from(format!("{} ({})", category, count).bold())),
+                Row::File(i) => {
+                    let entry = &self.entries[*i];
+                    let mark = if entry.included { "[x]" } else { "[ ]" };
+                    let line = format!("  {} {}", mark, entry.source.display());
+                    ListItem::new(if entry.included { Line::from(line) } else { Line::from(line.dim()) })
+                }
+            })
+            .collect();
+        let included = self.entries.iter().filter(|entry| entry.included).count();
+        let title = format!(" {} {} of {} files ", self.config.mode.imperative(), included, self.entries.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, list_area, &mut ListState::default().with_selected(selected_row));
+
+        let status = match (&self.editing, &self.message) {
+            (Some(category), _) => format!("Category: {}_  (Enter to set, Esc to cancel)", category),
+            (None, Some(message)) => message.clone(),
+            (None, None) => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
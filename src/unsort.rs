@@ -0,0 +1,93 @@
+use crate::config::{SortConfig, TransferMode};
+use crate::error::{display_chain, Result, SortError};
+use crate::journal::Journal;
+use crate::report::{FileMove, UnsortReport};
+use crate::transfer::{move_file, resolve_conflict};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Moves every file in the subdirectories of `config.target_dir` back up into it and removes the directories
+/// left empty, without needing the journal. Name collisions follow `config.on_conflict`, and the moves are
+/// journaled, so `undo` puts the files back into their folders. Hidden directories are left alone. In a dry run
+/// nothing is moved, and the report lists the moves that would have been made.
+pub fn unsort(config: &SortConfig) -> Result<UnsortReport> {
+    let target_dir = Path::new(&config.target_dir);
+    if !target_dir.is_dir() {
+        return Err(SortError::TargetNotADirectory(target_dir.to_path_buf()));
+    }
+
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(target_dir).map_err(SortError::io("Failed to read target directory"))? {
+        let entry = entry.map_err(SortError::io("Failed to read directory entry"))?;
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if is_dir && !entry.file_name().to_string_lossy().starts_with('.') {
+            subdirs.push(entry.path());
+        }
+    }
+    subdirs.sort();
+
+    let journal = Journal::new(target_dir, None);
+    let mut report = UnsortReport::default();
+    for subdir in &subdirs {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        walk(subdir, &mut files, &mut dirs)?;
+        report.files_found += files.len();
+        let category = subdir.file_name().unwrap_or_default().to_string_lossy();
+        let moved = |from: PathBuf, to: PathBuf| FileMove { from, to, category: category.to_string(), confidence: None };
+
+        for file in files {
+            let name = file.file_name().unwrap_or_default();
+            let Some(new_path) = resolve_conflict(&file, target_dir.join(name), config.on_conflict) else {
+                info!("Skipping '{}': already exists in the target directory", file.display());
+                continue;
+            };
+            if config.dry_run {
+                report.files.push(moved(file, new_path));
+                continue;
+            }
+            match move_file(&file, &new_path) {
+                Ok(()) => {
+                    info!("Moving '{}' -> '{}'", file.display(), new_path.display());
+                    if let Err(e) = journal.record(&file, &new_path, TransferMode::Move) {
+                        warn!("Could not journal move of '{}': {}", file.display(), display_chain(&e));
+                    }
+                    report.files.push(moved(file, new_path));
+                }
+                Err(e) => warn!("Failed to move '{}': {}", file.display(), e),
+            }
+        }
+
+        if !config.dry_run {
+            // Deepest first, so each directory is empty by the time it's tried; only succeeds if it is
+            dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+            for dir in dirs.iter().chain([subdir]) {
+                if fs::remove_dir(dir).is_ok() {
+                    info!("Removed empty directory '{}'", dir.display());
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Gathers the files below `dir` and its subdirectories, without following symlinks
+fn walk(dir: &Path, files: &mut Vec<PathBuf>, dirs: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(SortError::io(format!("Failed to read directory {:?}", dir)))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(SortError::io("Failed to read directory entry"))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            dirs.push(path.clone());
+            walk(&path, files, dirs)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
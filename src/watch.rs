@@ -0,0 +1,116 @@
+use crate::collect::{accepts_path, category_dirs, order_files, FileFilter};
+use crate::config::SortConfig;
+use crate::error::{display_chain, Result, SortError};
+use crate::history::record_history;
+use crate::llm::LlmBackend;
+use crate::report::SortReport;
+use crate::sort::{build_backends, build_client, sort_files};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::warn;
+
+/// Watches the target directory and sorts files as they appear, until `shutdown` completes.
+/// A file is only sorted once it has gone `--debounce-ms` without any events, so files that are still being
+/// written are left alone. When `shutdown` completes, files being sorted stop at the next one, as in
+/// [`sort_directory`](crate::sort_directory), files still waiting are left where they are, and the report says the
+/// watch was interrupted. `preview` is as for [`sort_directory`](crate::sort_directory).
+pub async fn watch_directory(config: &SortConfig, preview: bool, shutdown: impl Future<Output = ()>) -> Result<SortReport> {
+    config.validate()?;
+    let models = build_backends(build_client(config)?, config)?;
+    let filter = FileFilter::from_config(config)?;
+    // Events carry the watched path as their prefix, and the journal records absolute paths
+    let target_dir = std::path::absolute(&config.target_dir).map_err(SortError::io("Failed to resolve target directory"))?;
+    let debounce = Duration::from_millis(config.debounce_ms);
+
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // Only fails once the loop below is gone
+        let _ = sender.send(event);
+    })
+    .map_err(SortError::Watch)?;
+    let mode = if config.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    watcher.watch(&target_dir, mode).map_err(SortError::Watch)?;
+
+    // Shutdown has to reach a sort that's under way, not just the wait between them
+    let (stop, stopped) = watch::channel(false);
+    let stopped = || {
+        let mut stopped = stopped.clone();
+        // The sender outlives every wait
+        async move { let _ = stopped.wait_for(|&stopped| stopped).await; }
+    };
+    let shutdown = async {
+        shutdown.await;
+        stop.send_replace(true);
+        std::future::pending::<Infallible>().await
+    };
+    let watching = async {
+        // Files with recent activity, and when it was last seen
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        let mut report = SortReport::default();
+        loop {
+            let next_due = pending.values().min().map(|last| *last + debounce);
+            tokio::select! {
+                () = stopped() => return Ok::<_, SortError>(report),
+                Some(event) = events.recv() => match event {
+                    Ok(event) if is_file_activity(&event.kind) => {
+                        let now = Instant::now();
+                        pending.extend(event.paths.into_iter().map(|path| (path, now)));
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("{}", display_chain(&e)),
+                },
+                _ = sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                    let now = Instant::now();
+                    let settled = pending.extract_if(|_, last| now.duration_since(*last) >= debounce)
+                        .map(|(path, _)| path)
+                        .collect();
+                    report.merge(sort_settled(config, &models, &target_dir, &filter, settled, preview, stopped()).await?);
+                }
+            }
+        }
+    };
+
+    let mut report = tokio::select! {
+        report = watching => report?,
+        never = shutdown => match never {},
+    };
+    report.interrupted = true;
+    record_history(config, &report);
+    Ok(report)
+}
+
+/// Whether an event means a file may have been added or is still being written
+fn is_file_activity(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) => true,
+        EventKind::Modify(ModifyKind::Name(RenameMode::From) | ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        _ => false,
+    }
+}
+
+/// Sorts the settled files that are still there and eligible, in a run of their own
+async fn sort_settled(
+    config: &SortConfig,
+    models: &[(String, Box<dyn LlmBackend>)],
+    target_dir: &Path,
+    filter: &FileFilter,
+    paths: Vec<PathBuf>,
+    preview: bool,
+    stopped: impl Future<Output = ()>,
+) -> Result<SortReport> {
+    // Read afresh every time, since each run adds the categories it moved files into
+    let categories = category_dirs(target_dir)?;
+    let mut files: Vec<PathBuf> = paths.into_iter()
+        .filter(|path| accepts_path(path, target_dir, config, filter, &categories))
+        .collect();
+    order_files(&mut files, config.order);
+    sort_files(config, models, &files, preview, stopped).await
+}